@@ -1,35 +1,289 @@
 use std::collections::HashMap;
 use std::convert::Infallible;
 
-use crate::properties::PropValue;
-use crate::server::is_shutdown;
-use crate::state::save;
-use crate::utils::{json_response, ApiError, WarpResult};
-use crate::{instances::*, state};
+use crate::properties::{read_property, PropValue, ADMIN_PROPERTIES};
+use crate::server::{is_shutdown, is_tls};
+use crate::state::{backup, save};
+use crate::utils::{append_json_string, file_response, json_response, ApiError, WarpResult};
+use crate::schedules::ScheduleAction;
+use crate::auth::{Access, Role, User};
+use crate::loaders::{self, Flavor};
+use crate::{audit, auth, datapacks, files, import, instances::*, java, jobs, logs, mods, schedules, versions};
 use serde::Deserialize;
 use warp::Reply;
 
-static VERSION_CACHE: std::sync::RwLock<Option<&'static str>> = std::sync::RwLock::new(None);
-
 // APIS //
 
+#[derive(Deserialize)]
+pub struct Login {
+    name: String,
+    password: String,
+}
+
+/// the only api that does not need a session, responds with the token and also sets it as a cookie
+pub async fn login(body: Login) -> Result<WarpResult<impl Reply>, Infallible> {
+    let token = match auth::login(&body.name, &body.password) {
+        Ok(token) => token,
+        Err(error) => {
+            // slows down guessing passwords
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            return Ok(WarpResult::Err(error));
+        }
+    };
+    let mut body = String::with_capacity(128);
+    body += r#"{"token":"#;
+    append_json_string(&mut body, &token);
+    body += "}";
+    let cookie = format!(
+        "{}={token}; Path=/; HttpOnly; SameSite=Strict; Max-Age=604800{}",
+        auth::SESSION_COOKIE,
+        secure_cookie_flag()
+    );
+    Ok(WarpResult::Ok(warp::reply::with_header(
+        json_response(body),
+        "Set-Cookie",
+        cookie,
+    )))
+}
+
+pub async fn logout(token: Option<String>) -> Result<WarpResult<impl Reply>, Infallible> {
+    if let Some(token) = token {
+        auth::logout(&token);
+    }
+    let cookie = format!(
+        "{}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0{}",
+        auth::SESSION_COOKIE,
+        secure_cookie_flag()
+    );
+    Ok(WarpResult::Ok(warp::reply::with_header(
+        warp::reply(),
+        "Set-Cookie",
+        cookie,
+    )))
+}
+
+/// over https the browser must never send the session cookie in clear text
+fn secure_cookie_flag() -> &'static str {
+    if is_tls() {
+        "; Secure"
+    } else {
+        ""
+    }
+}
+
+/// responds with the name and role of the user that is logged in
+pub async fn session(token: Option<String>) -> Result<WarpResult<impl Reply>, Infallible> {
+    let Some(user) = token.as_deref().and_then(auth::session_user) else {
+        return Ok(WarpResult::Err(ApiError::Unauthorized));
+    };
+    let mut body = String::with_capacity(128);
+    body += r#"{"user":"#;
+    append_json_string(&mut body, &user.name);
+    body += r#","role":"#;
+    append_json_string(&mut body, user.role().name());
+    body += "}";
+    Ok(WarpResult::Ok(json_response(body)))
+}
+
+#[derive(Deserialize)]
+pub struct ChangePassword {
+    password: String,
+    new_password: String,
+}
+
+pub async fn change_password(
+    user: User,
+    body: ChangePassword,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = if auth::verify_password(&user.name, &body.password) {
+        auth::set_password(&user.name, &body.new_password)
+    } else {
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        Err(ApiError::BadCredentials)
+    };
+    audit::record(&user, "change_password", None, Some(&user.name), &result);
+    Ok(result.into())
+}
+
+pub fn users(user: User) -> WarpResult<impl Reply> {
+    if let Err(error) = user.require_admin() {
+        return WarpResult::Err(error);
+    }
+    WarpResult::Ok(json_response(auth::list_users()))
+}
+
+#[derive(Deserialize)]
+pub struct CreateUser {
+    name: String,
+    password: String,
+    role: Role,
+}
+
+pub async fn create_user(
+    user: User,
+    body: CreateUser,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = (|| {
+        user.require_admin()?;
+        auth::add_user(&body.name, &body.password, body.role)
+    })();
+    audit::record(&user, "create_user", None, Some(&body.name), &result);
+    Ok(result.into())
+}
+
+#[derive(Deserialize)]
+pub struct ModifyUser {
+    name: String,
+    role: Option<Role>,
+    password: Option<String>,
+    /// a `null` access removes the grant of that save
+    #[serde(default)]
+    grants: HashMap<String, Option<Access>>,
+}
+
+pub async fn modify_user(
+    user: User,
+    body: ModifyUser,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = (|| {
+        user.require_admin()?;
+        if body.grants.keys().any(|save| !is_safe(save)) {
+            return Err(ApiError::BadName);
+        }
+        // first, as demoting the last admin is refused
+        if let Some(role) = body.role {
+            auth::set_role(&body.name, role)?;
+        }
+        if let Some(password) = &body.password {
+            auth::set_password(&body.name, password)?;
+        }
+        for (save, access) in &body.grants {
+            auth::set_grant(&body.name, save, *access)?;
+        }
+        Ok(())
+    })();
+    audit::record(&user, "modify_user", None, Some(&body.name), &result);
+    Ok(result.into())
+}
+
+#[derive(Deserialize)]
+pub struct DeleteUser {
+    name: String,
+}
+
+pub async fn delete_user(
+    user: User,
+    body: DeleteUser,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = user
+        .require_admin()
+        .and_then(|()| auth::remove_user(&body.name));
+    audit::record(&user, "delete_user", None, Some(&body.name), &result);
+    Ok(result.into())
+}
+
 #[derive(Deserialize)]
 pub struct CreateSave {
     name: String,
     version: String,
     values: HashMap<String, PropValue>,
+    /// vanilla if missing
+    flavor: Option<String>,
+    /// the newest stable build of the flavor if missing
+    build: Option<String>,
 }
 
-pub async fn create_save(body: CreateSave) -> Result<WarpResult<impl Reply>, Infallible> {
-    if !is_safe(&body.name) {
-        return Ok(WarpResult::Err(ApiError::BadName));
+pub async fn create_save(
+    user: User,
+    body: CreateSave,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = async {
+        if !is_safe(&body.name) {
+            return Err(ApiError::BadName);
+        }
+        user.require_create()?;
+        let flavor = match &body.flavor {
+            Some(flavor) => Flavor::parse(flavor).ok_or(ApiError::BadRequest)?,
+            None => Flavor::Vanilla,
+        };
+        require_admin_properties(&user, &body.values)?;
+        let build = loaders::resolve(flavor, &body.version, body.build.as_deref()).await?;
+        versions::download(&body.version).await?;
+        let mut response = save::create(&body.name, &body.version, body.values)?;
+        // whoever creates a save can manage it, even if their role could not
+        if user.access(&body.name) < Access::Manage {
+            auth::set_grant(&user.name, &body.name, Some(Access::Manage))?;
+        }
+        if flavor != Flavor::Vanilla {
+            let lock = save::lock(&body.name)?;
+            let job = jobs::start_install(lock, &body.name, flavor, &body.version, &build);
+            response.pop();
+            response += &format!(r#","job":{job}}}"#);
+        }
+        Ok(json_response(response))
     }
-    if let Err(error) = state::download_version(&body.version).await {
-        return Ok(WarpResult::Err(error.into()));
+    .await;
+    audit::record(
+        &user,
+        "create_save",
+        Some(&body.name),
+        Some(&body.version),
+        &result,
+    );
+    Ok(result.into())
+}
+
+#[derive(Deserialize)]
+pub struct ImportSave {
+    /// the version of the server, if missing it is read from the level.dat of the world
+    version: Option<String>,
+}
+
+/// creates a save from a zipped singleplayer world or server folder, sent as the body of the request
+pub async fn import_save(
+    name: String,
+    user: User,
+    query: ImportSave,
+    body: warp::hyper::body::Bytes,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let name = parse_name(name);
+    let result = async {
+        let name = name.clone()?;
+        user.require_create()?;
+        let data = body.clone();
+        let layout = tokio::task::spawn_blocking(move || import::inspect(&data))
+            .await
+            .unwrap_or_else(|error| Err(ApiError::IOError(error.to_string())))?;
+        // a server folder can have jars and libraries that end up running on the machine
+        if !layout.world {
+            user.require_admin()?;
+        }
+        let version = match query.version.as_ref().or(layout.version.as_ref()) {
+            Some(version) => version.clone(),
+            None => return Err(ApiError::ImportVersionUnknown),
+        };
+        versions::download(&version).await?;
+        let owned_name = name.clone();
+        let response = tokio::task::spawn_blocking(move || {
+            import::create(&owned_name, &body, &layout, &version)
+        })
+        .await
+        .unwrap_or_else(|error| Err(ApiError::IOError(error.to_string())))?;
+        // whoever creates a save can manage it, even if their role could not
+        if user.access(&name) < Access::Manage {
+            auth::set_grant(&user.name, &name, Some(Access::Manage))?;
+        }
+        Ok(json_response(response))
     }
-    Ok(save::create(&body.name, &body.version, body.values)
-        .map(json_response)
-        .into())
+    .await;
+    audit::record(
+        &user,
+        "import_save",
+        name.as_deref().ok(),
+        query.version.as_deref(),
+        &result,
+    );
+    Ok(result.into())
 }
 
 #[derive(Deserialize)]
@@ -38,17 +292,26 @@ pub struct ModifySave {
     values: HashMap<String, PropValue>,
 }
 
-pub async fn modify_save(body: ModifySave) -> Result<WarpResult<impl Reply>, Infallible> {
-    if !is_safe(&body.name) {
-        return Ok(WarpResult::Err(ApiError::BadName));
-    }
-    match query_instance(&body.name).await {
-        Ok(InstanceStatus::Offline | InstanceStatus::Cold) => {
-            Ok(save::modify(&body.name, body.values).into())
+pub async fn modify_save(
+    user: User,
+    body: ModifySave,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = async {
+        if !is_safe(&body.name) {
+            return Err(ApiError::BadName);
+        }
+        user.require(&body.name, Access::Manage)?;
+        require_admin_properties(&user, &body.values)?;
+        match query_instance(&body.name).await? {
+            InstanceStatus::Offline | InstanceStatus::Crashed | InstanceStatus::Cold => {
+                save::modify(&body.name, body.values)
+            }
+            status => Err(status.to_error()),
         }
-        Ok(status) => Ok(WarpResult::Err(status.to_error())),
-        Err(error) => Ok(WarpResult::Err(error)),
     }
+    .await;
+    audit::record(&user, "modify_save", Some(&body.name), None, &result);
+    Ok(result.into())
 }
 
 #[derive(Deserialize)]
@@ -56,15 +319,27 @@ pub struct DeleteSave {
     name: String,
 }
 
-pub async fn delete_save(body: DeleteSave) -> Result<WarpResult<impl Reply>, Infallible> {
-    if !is_safe(&body.name) {
-        return Ok(WarpResult::Err(ApiError::BadName));
-    }
-    match query_instance(&body.name).await {
-        Ok(InstanceStatus::Offline | InstanceStatus::Cold) => Ok(save::delete(&body.name).into()),
-        Ok(status) => Ok(WarpResult::Err(status.to_error())),
-        Err(error) => Ok(WarpResult::Err(error)),
+pub async fn delete_save(
+    user: User,
+    body: DeleteSave,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = async {
+        if !is_safe(&body.name) {
+            return Err(ApiError::BadName);
+        }
+        user.require(&body.name, Access::Manage)?;
+        match query_instance(&body.name).await? {
+            InstanceStatus::Offline | InstanceStatus::Crashed | InstanceStatus::Cold => {
+                save::delete(&body.name)?;
+                schedules::delete_all(&body.name)?;
+                auth::remove_grants(&body.name)
+            }
+            status => Err(status.to_error()),
+        }
     }
+    .await;
+    audit::record(&user, "delete_save", Some(&body.name), None, &result);
+    Ok(result.into())
 }
 
 #[derive(Deserialize)]
@@ -72,11 +347,17 @@ pub struct StartSave {
     name: String,
 }
 
-pub async fn start_save(body: StartSave) -> Result<WarpResult<impl Reply>, Infallible> {
-    if !is_safe(&body.name) {
-        return Ok(WarpResult::Err(ApiError::BadName));
+pub async fn start_save(user: User, body: StartSave) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = async {
+        if !is_safe(&body.name) {
+            return Err(ApiError::BadName);
+        }
+        user.require(&body.name, Access::Operate)?;
+        start_instance(&body.name).await
     }
-    Ok(start_instance(&body.name).await.into())
+    .await;
+    audit::record(&user, "start_save", Some(&body.name), None, &result);
+    Ok(result.into())
 }
 
 #[derive(Deserialize)]
@@ -84,84 +365,685 @@ pub struct StopSave {
     name: String,
 }
 
-pub async fn stop_save(body: StopSave) -> Result<WarpResult<impl Reply>, Infallible> {
-    if !is_safe(&body.name) {
-        return Ok(WarpResult::Err(ApiError::BadName));
+pub async fn stop_save(user: User, body: StopSave) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = async {
+        if !is_safe(&body.name) {
+            return Err(ApiError::BadName);
+        }
+        user.require(&body.name, Access::Operate)?;
+        stop_instance(&body.name).await
+    }
+    .await;
+    audit::record(&user, "stop_save", Some(&body.name), None, &result);
+    Ok(result.into())
+}
+
+#[derive(Deserialize)]
+pub struct KillSave {
+    name: String,
+}
+
+pub async fn kill_save(user: User, body: KillSave) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = async {
+        if !is_safe(&body.name) {
+            return Err(ApiError::BadName);
+        }
+        user.require(&body.name, Access::Operate)?;
+        kill_instance(&body.name).await
+    }
+    .await;
+    audit::record(&user, "kill_save", Some(&body.name), None, &result);
+    Ok(result.into())
+}
+
+pub async fn backups(save: String, user: User) -> Result<WarpResult<impl Reply>, Infallible> {
+    let save = match parse_name(save) {
+        Ok(save) => save,
+        Err(error) => return Ok(WarpResult::Err(error)),
+    };
+    if let Err(error) = user.require(&save, Access::View) {
+        return Ok(WarpResult::Err(error));
     }
-    Ok(stop_instance(&body.name).await.into())
+    Ok(backup::list(&save).map(json_response).into())
+}
+
+#[derive(Deserialize)]
+pub struct CreateBackup {
+    name: String,
 }
 
-pub async fn versions() -> Result<WarpResult<impl Reply>, Infallible> {
-    if let Some(versions) = *VERSION_CACHE.read().unwrap() {
-        return Ok(WarpResult::Ok(json_response(versions)));
+/// starts the backup in the background, responds with the job that can be polled with `job`
+pub async fn create_backup(
+    user: User,
+    body: CreateBackup,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = async {
+        if !is_safe(&body.name) {
+            return Err(ApiError::BadName);
+        }
+        user.require(&body.name, Access::Operate)?;
+        match query_instance(&body.name).await? {
+            InstanceStatus::Online
+            | InstanceStatus::Offline
+            | InstanceStatus::Crashed
+            | InstanceStatus::Cold => Ok(jobs::start_backup(&body.name)),
+            status => Err(status.to_error()),
+        }
     }
-    let response = match async {
-        reqwest::Client::new()
-            .get("https://mcversions.net")
-            .send()
-            .await?
-            .text()
+    .await;
+    audit::record(&user, "create_backup", Some(&body.name), None, &result);
+    Ok(result
+        .map(|job| json_response(format!(r#"{{"job":{job}}}"#)))
+        .into())
+}
+
+#[derive(Deserialize)]
+pub struct RestoreBackup {
+    name: String,
+    backup: String,
+}
+
+pub async fn restore_backup(
+    user: User,
+    body: RestoreBackup,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = async {
+        if !is_safe(&body.name) || !is_safe(&body.backup) {
+            return Err(ApiError::BadName);
+        }
+        user.require(&body.name, Access::Manage)?;
+        // held until the restore finishes, so the server can not be started in the meantime
+        let _lock = save::lock(&body.name)?;
+        match query_instance(&body.name).await? {
+            InstanceStatus::Offline | InstanceStatus::Crashed | InstanceStatus::Cold => {}
+            status => return Err(status.to_error()),
+        }
+        let (name, id) = (body.name.clone(), body.backup.clone());
+        tokio::task::spawn_blocking(move || backup::restore(&name, &id))
             .await
+            .unwrap_or_else(|error| Err(ApiError::IOError(error.to_string())))
     }
-    .await
-    {
-        Ok(response) => response,
-        Err(error) => return Ok(WarpResult::Err(ApiError::IOError(error.to_string()))),
+    .await;
+    audit::record(
+        &user,
+        "restore_backup",
+        Some(&body.name),
+        Some(&body.backup),
+        &result,
+    );
+    Ok(result.into())
+}
+
+#[derive(Deserialize)]
+pub struct DeleteBackup {
+    name: String,
+    backup: String,
+}
+
+pub async fn delete_backup(
+    user: User,
+    body: DeleteBackup,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = (|| {
+        if !is_safe(&body.name) || !is_safe(&body.backup) {
+            return Err(ApiError::BadName);
+        }
+        user.require(&body.name, Access::Manage)?;
+        backup::delete(&body.name, &body.backup)
+    })();
+    audit::record(
+        &user,
+        "delete_backup",
+        Some(&body.name),
+        Some(&body.backup),
+        &result,
+    );
+    Ok(result.into())
+}
+
+#[derive(Deserialize)]
+pub struct UpgradeSave {
+    name: String,
+    version: String,
+    /// allows going back to an older version, which may corrupt the world
+    #[serde(default)]
+    force: bool,
+}
+
+/// starts changing the version in the background, responds with the job that can be polled with `job`
+pub async fn upgrade_save(
+    user: User,
+    body: UpgradeSave,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = async {
+        if !is_safe(&body.name) {
+            return Err(ApiError::BadName);
+        }
+        user.require(&body.name, Access::Manage)?;
+        match query_instance(&body.name).await? {
+            InstanceStatus::Offline | InstanceStatus::Crashed | InstanceStatus::Cold => {}
+            status => return Err(status.to_error()),
+        }
+        save::check_busy(&body.name)?;
+        let path = format!("saves/{}/server.properties", body.name);
+        // the builds of mod loaders are made for one minecraft version
+        let flavor = read_property(&path, "mc-manager-server-flavor")?.unwrap_or_default();
+        if !matches!(flavor.trim(), "" | "vanilla") {
+            return Err(ApiError::FlavorNotSupported);
+        }
+        let current = read_property(&path, "mc-manager-server-version")?.unwrap_or_default();
+        versions::check_upgrade(current.trim(), &body.version, body.force).await?;
+        Ok(jobs::start_upgrade(&body.name, &body.version))
+    }
+    .await;
+    let detail = if body.force {
+        format!("{} (forced)", body.version)
+    } else {
+        body.version.clone()
     };
+    audit::record(
+        &user,
+        "upgrade_save",
+        Some(&body.name),
+        Some(&detail),
+        &result,
+    );
+    Ok(result
+        .map(|job| json_response(format!(r#"{{"job":{job}}}"#)))
+        .into())
+}
 
-    let existing_versions: Result<Vec<String>, std::io::Error> = (|| {
-        let mut versions = Vec::new();
-        for path in std::fs::read_dir("versions")? {
-            let path = path?;
-            if let Some(filename) = path.file_name().to_str() {
-                if matches!(path.metadata(), Ok(md) if md.is_file()) {
-                    if let Some(name) = filename.strip_suffix(".jar") {
-                        versions.push(name.to_owned());
-                    }
-                }
-            }
+pub fn jobs(user: User) -> WarpResult<impl Reply> {
+    WarpResult::Ok(json_response(jobs::summary(|save| {
+        user.access(save) >= Access::View
+    })))
+}
+
+pub fn job(id: u64, user: User) -> WarpResult<impl Reply> {
+    jobs::query(id, |save| user.access(save) >= Access::View)
+        .map(json_response)
+        .into()
+}
+
+pub fn schedules(user: User) -> WarpResult<impl Reply> {
+    WarpResult::Ok(json_response(schedules::list(|save| {
+        user.access(save) >= Access::View
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct CreateSchedule {
+    name: String,
+    cron: String,
+    action: ScheduleAction,
+    #[serde(default = "default_true")]
+    enabled: bool,
+}
+
+pub async fn create_schedule(
+    user: User,
+    body: CreateSchedule,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = (|| {
+        if !is_safe(&body.name) {
+            return Err(ApiError::BadName);
         }
-        Ok(versions)
+        user.require(&body.name, Access::Manage)?;
+        schedules::create(&body.name, body.cron, body.action, body.enabled)
     })();
-    let mut existing_versions = match existing_versions {
-        Ok(versions) => versions,
-        Err(error) => return Ok(WarpResult::Err(error.into())),
+    let detail = result.as_ref().ok().map(|id| format!("schedule {id}"));
+    audit::record(
+        &user,
+        "create_schedule",
+        Some(&body.name),
+        detail.as_deref(),
+        &result,
+    );
+    Ok(result
+        .map(|id| json_response(format!(r#"{{"id":{id}}}"#)))
+        .into())
+}
+
+#[derive(Deserialize)]
+pub struct ModifySchedule {
+    id: u64,
+    cron: Option<String>,
+    action: Option<ScheduleAction>,
+    enabled: Option<bool>,
+}
+
+pub async fn modify_schedule(
+    user: User,
+    body: ModifySchedule,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let save = schedules::save_of(body.id);
+    let result = save.clone().and_then(|save| {
+        user.require(&save, Access::Manage)?;
+        schedules::modify(body.id, body.cron, body.action, body.enabled)
+    });
+    let detail = format!("schedule {}", body.id);
+    audit::record(
+        &user,
+        "modify_schedule",
+        save.ok().as_deref(),
+        Some(&detail),
+        &result,
+    );
+    Ok(result.into())
+}
+
+#[derive(Deserialize)]
+pub struct DeleteSchedule {
+    id: u64,
+}
+
+pub async fn delete_schedule(
+    user: User,
+    body: DeleteSchedule,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let save = schedules::save_of(body.id);
+    let result = save.clone().and_then(|save| {
+        user.require(&save, Access::Manage)?;
+        schedules::delete(body.id)
+    });
+    let detail = format!("schedule {}", body.id);
+    audit::record(
+        &user,
+        "delete_schedule",
+        save.ok().as_deref(),
+        Some(&detail),
+        &result,
+    );
+    Ok(result.into())
+}
+
+/// only admins can read the audit log
+pub async fn audit(
+    user: User,
+    filter: audit::Filter,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    Ok(user
+        .require_admin()
+        .and_then(|()| audit::query(&filter))
+        .map(json_response)
+        .into())
+}
+
+pub fn logs(save: String, user: User) -> WarpResult<impl Reply> {
+    parse_name(save)
+        .and_then(|save| user.require(&save, Access::View).map(|()| save))
+        .and_then(|save| logs::list(&save))
+        .map(json_response)
+        .into()
+}
+
+pub async fn read_log(
+    save: String,
+    source: String,
+    file: String,
+    user: User,
+    range: logs::Range,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = async {
+        let save = parse_name(save)?;
+        user.require(&save, Access::View)?;
+        let source = logs::Source::parse(&source).ok_or(ApiError::LogNotFound)?;
+        let file = parse_name(file)?;
+        tokio::task::spawn_blocking(move || logs::read(&save, source, &file, &range))
+            .await
+            .unwrap_or_else(|error| Err(ApiError::IOError(error.to_string())))
+    }
+    .await;
+    Ok(result.map(json_response).into())
+}
+
+pub async fn search_logs(
+    save: String,
+    user: User,
+    search: logs::Search,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = async {
+        let save = parse_name(save)?;
+        user.require(&save, Access::View)?;
+        tokio::task::spawn_blocking(move || logs::search(&save, &search))
+            .await
+            .unwrap_or_else(|error| Err(ApiError::IOError(error.to_string())))
+    }
+    .await;
+    Ok(result.map(json_response).into())
+}
+
+/// the plugins or mods of the save, depending on its flavor
+pub fn mods(save: String, user: User) -> WarpResult<impl Reply> {
+    parse_name(save)
+        .and_then(|save| user.require(&save, Access::View).map(|()| save))
+        .and_then(|save| mods::list(&save))
+        .map(json_response)
+        .into()
+}
+
+/// adds or replaces a jar, sent as the body of the request, only admins can add code to a save
+pub async fn upload_mod(
+    save: String,
+    file: String,
+    user: User,
+    body: warp::hyper::body::Bytes,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let save = parse_name(save);
+    let file = parse_name(file);
+    let result = async {
+        let (save, file) = (save.clone()?, file.clone()?);
+        if !is_safe(&save) || !is_safe(&file) {
+            return Err(ApiError::BadName);
+        }
+        // the jar runs on the machine when the server starts
+        user.require_admin()?;
+        require_stopped(&save).await?;
+        mods::upload(&save, &file, &body)
+    }
+    .await;
+    audit::record(
+        &user,
+        "upload_mod",
+        save.as_deref().ok(),
+        file.as_deref().ok(),
+        &result,
+    );
+    Ok(result.into())
+}
+
+#[derive(Deserialize)]
+pub struct ToggleMod {
+    name: String,
+    file: String,
+    enabled: bool,
+}
+
+pub async fn toggle_mod(
+    user: User,
+    body: ToggleMod,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = async {
+        if !is_safe(&body.name) || !is_safe(&body.file) {
+            return Err(ApiError::BadName);
+        }
+        user.require(&body.name, Access::Manage)?;
+        require_stopped(&body.name).await?;
+        mods::set_enabled(&body.name, &body.file, body.enabled)
+    }
+    .await;
+    let action = if body.enabled { "enable_mod" } else { "disable_mod" };
+    audit::record(&user, action, Some(&body.name), Some(&body.file), &result);
+    Ok(result.into())
+}
+
+#[derive(Deserialize)]
+pub struct DeleteMod {
+    name: String,
+    file: String,
+}
+
+pub async fn delete_mod(
+    user: User,
+    body: DeleteMod,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = async {
+        if !is_safe(&body.name) || !is_safe(&body.file) {
+            return Err(ApiError::BadName);
+        }
+        user.require(&body.name, Access::Manage)?;
+        require_stopped(&body.name).await?;
+        mods::delete(&body.name, &body.file)
+    }
+    .await;
+    audit::record(&user, "delete_mod", Some(&body.name), Some(&body.file), &result);
+    Ok(result.into())
+}
+
+pub fn datapacks(save: String, user: User) -> WarpResult<impl Reply> {
+    parse_name(save)
+        .and_then(|save| user.require(&save, Access::View).map(|()| save))
+        .and_then(|save| datapacks::list(&save))
+        .map(json_response)
+        .into()
+}
+
+/// adds or replaces a zipped pack, sent as the body of the request
+///
+/// if the server is online it reloads, which finds the new pack and enables it
+pub async fn upload_datapack(
+    save: String,
+    file: String,
+    user: User,
+    body: warp::hyper::body::Bytes,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let save = parse_name(save);
+    let file = parse_name(file);
+    let result = async {
+        let (save, file) = (save.clone()?, file.clone()?);
+        if !is_safe(&save) || !is_safe(&file) {
+            return Err(ApiError::BadName);
+        }
+        user.require(&save, Access::Manage)?;
+        save::check_busy(&save)?;
+        datapacks::upload(&save, &file, &body)?;
+        if query_instance(&save).await? == InstanceStatus::Online {
+            write_instance(&save, "/reload").await?;
+        }
+        Ok(())
+    }
+    .await;
+    audit::record(
+        &user,
+        "upload_datapack",
+        save.as_deref().ok(),
+        file.as_deref().ok(),
+        &result,
+    );
+    Ok(result.into())
+}
+
+#[derive(Deserialize)]
+pub struct ToggleDatapack {
+    name: String,
+    /// the name of the zip or folder, without the `file/` of its id
+    pack: String,
+    enabled: bool,
+}
+
+/// packs are enabled in the world, which only the server can change, so it must be online
+pub async fn toggle_datapack(
+    user: User,
+    body: ToggleDatapack,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = async {
+        if !is_safe(&body.name) || !is_safe(&body.pack) {
+            return Err(ApiError::BadName);
+        }
+        user.require(&body.name, Access::Manage)?;
+        datapacks::exists(&body.name, &body.pack)?;
+        match query_instance(&body.name).await? {
+            InstanceStatus::Online => {}
+            status => return Err(status.to_error()),
+        }
+        let action = if body.enabled { "enable" } else { "disable" };
+        write_instance(&body.name, &format!(r#"/datapack {action} "file/{}""#, body.pack)).await
+    }
+    .await;
+    let action = if body.enabled {
+        "enable_datapack"
+    } else {
+        "disable_datapack"
     };
+    audit::record(&user, action, Some(&body.name), Some(&body.pack), &result);
+    Ok(result.into())
+}
+
+#[derive(Deserialize)]
+pub struct DeleteDatapack {
+    name: String,
+    pack: String,
+}
 
-    let mut versions = String::new();
-    let mut online_versions = String::new();
-    for version in response.split("data-version=\"").skip(1) {
-        if !version.starts_with(|x: char| x.is_ascii_digit()) {
-            continue;
+/// if the server is online the pack is disabled before it is removed
+pub async fn delete_datapack(
+    user: User,
+    body: DeleteDatapack,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = async {
+        if !is_safe(&body.name) || !is_safe(&body.pack) {
+            return Err(ApiError::BadName);
         }
-        let Some((version, _)) = version.split_once('"') else {
-            continue;
-        };
-        if let Some(index) = existing_versions.iter().position(|x| x == version) {
-            existing_versions.remove(index);
+        user.require(&body.name, Access::Manage)?;
+        save::check_busy(&body.name)?;
+        datapacks::exists(&body.name, &body.pack)?;
+        if query_instance(&body.name).await? == InstanceStatus::Online {
+            write_instance(&body.name, &format!(r#"/datapack disable "file/{}""#, body.pack)).await?;
         }
-        online_versions.push_str("\"");
-        online_versions.push_str(version);
-        online_versions.push_str("\",");
+        datapacks::delete(&body.name, &body.pack)
     }
-    versions.push('[');
-    for i in &existing_versions {
-        online_versions.push_str("\"");
-        online_versions.push_str(&i);
-        online_versions.push_str("\",");
+    .await;
+    audit::record(&user, "delete_datapack", Some(&body.name), Some(&body.pack), &result);
+    Ok(result.into())
+}
+
+/// lists a directory of the save, or downloads a file
+pub async fn read_files(
+    save: String,
+    path: warp::path::Tail,
+    user: User,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = async {
+        let save = parse_name(save)?;
+        let path = parse_path(path.as_str())?;
+        user.require(&save, Access::Manage)?;
+        match files::read(&save, &path).await? {
+            files::Entry::Directory(listing) => Ok(json_response(listing)),
+            files::Entry::File(file, size) => {
+                let name = path.last().map_or(save.as_str(), String::as_str);
+                Ok(file_response(file, name, size))
+            }
+        }
+    }
+    .await;
+    Ok(result.into())
+}
+
+/// creates or replaces a file of the save with the body of the request, only admins can change files
+pub async fn upload_file(
+    save: String,
+    path: warp::path::Tail,
+    user: User,
+    body: warp::hyper::body::Bytes,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let save = parse_name(save);
+    let path = parse_path(path.as_str());
+    let result = (|| {
+        let (save, path) = (save.clone()?, path.clone()?);
+        // jars and the argument files of the loaders can be replaced, which runs any code
+        user.require_admin()?;
+        save::check_busy(&save)?;
+        files::write(&save, &path, &body)
+    })();
+    let detail = path.as_ref().map(|path| path.join("/"));
+    audit::record(
+        &user,
+        "upload_file",
+        save.as_deref().ok(),
+        detail.as_deref().ok(),
+        &result,
+    );
+    Ok(result.into())
+}
+
+#[derive(Deserialize)]
+pub struct DeleteFile {
+    name: String,
+    /// relative to the folder of the save, separated by /
+    path: String,
+}
+
+pub async fn delete_file(
+    user: User,
+    body: DeleteFile,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = (|| {
+        if !is_safe(&body.name) {
+            return Err(ApiError::BadName);
+        }
+        let path = parse_path(&body.path)?;
+        user.require_admin()?;
+        save::check_busy(&body.name)?;
+        files::delete(&body.name, &path)
+    })();
+    audit::record(&user, "delete_file", Some(&body.name), Some(&body.path), &result);
+    Ok(result.into())
+}
+
+/// fails with Forbidden if a property in `ADMIN_PROPERTIES` is being changed by someone else
+fn require_admin_properties(user: &User, values: &HashMap<String, PropValue>) -> Result<(), ApiError> {
+    if values.keys().any(|key| ADMIN_PROPERTIES.contains(&key.as_str())) {
+        user.require_admin()?;
     }
-    versions.push_str(&online_versions);
-    if versions.ends_with(',') {
-        versions.pop();
+    Ok(())
+}
+
+/// the jars are loaded when the server starts, and may be locked while it runs
+async fn require_stopped(name: &str) -> Result<(), ApiError> {
+    match query_instance(name).await? {
+        InstanceStatus::Offline | InstanceStatus::Crashed | InstanceStatus::Cold => {}
+        status => return Err(status.to_error()),
     }
-    versions.push(']');
+    save::check_busy(name)
+}
+
+fn default_true() -> bool {
+    true
+}
 
-    let versions = *VERSION_CACHE
-        .write()
-        .unwrap()
-        .get_or_insert_with(|| versions.leak());
+pub async fn versions(_user: User) -> Result<WarpResult<impl Reply>, Infallible> {
+    Ok(versions::list(false).await.map(json_response).into())
+}
+
+#[derive(Deserialize)]
+pub struct RefreshVersions {}
 
-    Ok(WarpResult::Ok(json_response(versions)))
+/// the saves of each version are only listed if the user can see them
+pub fn installed_versions(user: User) -> WarpResult<impl Reply> {
+    versions::list_installed(|save| user.access(save) >= Access::View)
+        .map(json_response)
+        .into()
+}
+
+#[derive(Deserialize)]
+pub struct DeleteVersion {
+    version: String,
+}
+
+/// only admins can delete versions, as they are shared by everyone
+pub async fn delete_version(
+    user: User,
+    body: DeleteVersion,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = user
+        .require_admin()
+        .and_then(|()| versions::delete(&body.version));
+    audit::record(&user, "delete_version", None, Some(&body.version), &result);
+    Ok(result.into())
+}
+
+/// fetches the version manifest again, even if the cached one has not expired
+pub async fn refresh_versions(
+    user: User,
+    _body: RefreshVersions,
+) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = async {
+        user.require_create()?;
+        versions::list(true).await
+    }
+    .await;
+    audit::record(&user, "refresh_versions", None, None, &result);
+    Ok(result.map(json_response).into())
 }
 
 /*
@@ -187,12 +1069,16 @@ pub fn versions() -> WarpResult<impl Reply> {
 }
 */
 
-pub async fn saves() -> Result<WarpResult<impl Reply>, Infallible> {
-    Ok((|| async {
+/// only lists the saves the user can see
+pub async fn saves(user: User) -> Result<WarpResult<impl Reply>, Infallible> {
+    Ok(async {
         let mut body = String::with_capacity(16 * 1024);
         body.push_str("{\"saves\":[");
         for name in save::iter()? {
             let name = name?;
+            if user.access(&name) < Access::View {
+                continue;
+            }
             body.push_str(&save::load(&name, query_instance(&name).await?)?);
             body.push(',');
         }
@@ -202,17 +1088,20 @@ pub async fn saves() -> Result<WarpResult<impl Reply>, Infallible> {
             _ => unreachable!(),
         }
         body.push('}');
-        Ok(json_response(body))
-    })()
+        Ok::<_, ApiError>(json_response(body))
+    }
     .await
     .into())
 }
 
-pub fn icons(save: String) -> WarpResult<impl Reply> {
+pub fn icons(save: String, user: User) -> WarpResult<impl Reply> {
     let save = match parse_name(save) {
         Ok(save) => save,
         Err(error) => return WarpResult::Err(error),
     };
+    if let Err(error) = user.require(&save, Access::View) {
+        return WarpResult::Err(error);
+    }
     const UNKNOWN_PNG: &[u8] = include_bytes!("../static/assets/unknown.png");
     let data = match std::fs::read(format!("saves/{save}/world/icon.png")) {
         Ok(data) => data,
@@ -225,13 +1114,18 @@ pub fn icons(save: String) -> WarpResult<impl Reply> {
     ))
 }
 
-pub fn schema() -> WarpResult<impl Reply> {
+/// the java runtimes a save can be pinned to with `mc-manager-java`
+pub fn runtimes(_user: User) -> WarpResult<impl Reply> {
+    WarpResult::Ok(json_response(java::list()))
+}
+
+pub fn schema(_user: User) -> WarpResult<impl Reply> {
     WarpResult::Ok(json_response(save::schema()))
 }
 
-pub async fn status() -> Result<WarpResult<impl Reply>, Infallible> {
+pub async fn status(user: User) -> Result<WarpResult<impl Reply>, Infallible> {
     Ok(WarpResult::Ok(json_response(
-        instance_status_summary().await,
+        instance_status_summary(|save| user.access(save) >= Access::View).await,
     )))
 }
 
@@ -241,16 +1135,29 @@ pub struct Command {
     command: String,
 }
 
-pub async fn command(body: Command) -> Result<WarpResult<impl Reply>, Infallible> {
-    if !is_safe(&body.name) {
-        return Ok(WarpResult::Err(ApiError::BadName));
+pub async fn command(user: User, body: Command) -> Result<WarpResult<impl Reply>, Infallible> {
+    let result = async {
+        if !is_safe(&body.name) {
+            return Err(ApiError::BadName);
+        }
+        user.require(&body.name, Access::Manage)?;
+        write_instance(&body.name, &body.command).await
     }
-    Ok(write_instance(&body.name, &body.command).await.into())
+    .await;
+    audit::record(
+        &user,
+        "command",
+        Some(&body.name),
+        Some(&body.command),
+        &result,
+    );
+    Ok(result.into())
 }
 
 pub async fn console(
     mut offset: usize,
     save: String,
+    user: User,
     ws: warp::ws::Ws,
 ) -> Result<WarpResult<impl Reply>, Infallible> {
     #[cfg(debug_assertions)]
@@ -261,6 +1168,9 @@ pub async fn console(
         Ok(save) => save,
         Err(error) => return Ok(WarpResult::Err(error)),
     };
+    if let Err(error) = user.require(&save, Access::View) {
+        return Ok(WarpResult::Err(error));
+    }
     if DEBUG_WEB_SOCKET {
         println!("[*] Websocket: reading console of {}", &save);
     }
@@ -272,12 +1182,32 @@ pub async fn console(
             use futures::SinkExt;
             let mut subscription = vector.subscribe();
             while !is_shutdown() {
+                // the output is no longer in memory, it is sent from the logs
+                let start = subscription.borrow().start;
+                if offset < start {
+                    let (from, payload) =
+                        vector.replay(offset, start).unwrap_or((start, Vec::new()));
+                    // part of the output was lost, tells the client where the output continues
+                    if from != offset {
+                        let message = warp::ws::Message::text(format!(r#"{{"offset":{from}}}"#));
+                        if ws.send(message).await.is_err() {
+                            break;
+                        }
+                        offset = from;
+                    }
+                    if !payload.is_empty() {
+                        let len = payload.len();
+                        if ws.send(warp::ws::Message::binary(payload)).await.is_err() {
+                            break;
+                        }
+                        offset += len;
+                    }
+                    continue;
+                }
                 let pair = {
                     let borrow = subscription.borrow();
-                    let data = &borrow.0;
-                    let alive = &borrow.1;
-                    if offset >= data.len() {
-                        if !*alive {
+                    if offset >= borrow.end() {
+                        if !borrow.alive {
                             if DEBUG_WEB_SOCKET {
                                 println!("[*] Websocket stream finished");
                             }
@@ -285,7 +1215,7 @@ pub async fn console(
                         }
                         None
                     } else {
-                        Some((data[offset..].to_owned(), data.len()))
+                        Some((borrow.since(offset).to_owned(), borrow.end()))
                     }
                 };
                 if let Some((payload, new_offset)) = pair {
@@ -373,7 +1303,7 @@ fn is_safe(text: &str) -> bool {
     for byte in text.as_bytes() {
         if matches!(
             *byte,
-            0..=31 | 127 | b'/' | b'\\' | b':' | b'*' | b'?' | b'"' | b'<' | b'>'
+            0..=31 | 127 | b'/' | b'\\' | b':' | b'*' | b'?' | b'"' | b'<' | b'>' | b'|'
         ) {
             return false;
         }
@@ -383,8 +1313,90 @@ fn is_safe(text: &str) -> bool {
         "COM7", "COM8", "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8",
         "LPT9",
     ];
-    if INVALID.iter().any(|x| text.eq_ignore_ascii_case(x)) {
+    // windows also reserves the device names followed by an extension, like nul.txt
+    let stem = text.split('.').next().unwrap_or(text);
+    if INVALID.iter().any(|x| text.eq_ignore_ascii_case(x) || stem.eq_ignore_ascii_case(x)) {
         return false;
     }
     true
 }
+
+/// splits a path relative to a save into its parts, each one must be safe, an empty path is the save itself
+fn parse_path(path: &str) -> Result<Vec<String>, ApiError> {
+    path.split('/')
+        .filter(|part| !part.is_empty())
+        .map(|part| parse_name(part.to_owned()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAFE: &[&str] = &[
+        "world",
+        "server.properties",
+        "My World",
+        "a..b",
+        ".hidden",
+        "nul_",
+    ];
+
+    const UNSAFE: &[&str] = &[
+        "",
+        ".",
+        "..",
+        "a/b",
+        "a\\b",
+        "c:",
+        "a*",
+        "a?",
+        "a\"",
+        "<a>",
+        "a|b",
+        "a\0",
+        "a\n",
+        "\x7f",
+        " a",
+        "a ",
+        "a.",
+        "mundo\u{e9}",
+        "CON",
+        "con",
+        "Nul.txt",
+        "com1.dat",
+        "LPT9",
+    ];
+
+    #[test]
+    fn safe_names() {
+        for name in SAFE {
+            assert!(is_safe(name), "{name}");
+        }
+        for name in UNSAFE {
+            assert!(!is_safe(name), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn decodes_names() {
+        let parse = |name: &str| parse_name(name.to_owned());
+        assert!(parse("My%20World") == Ok("My World".to_owned()));
+        assert!(parse("100%25") == Ok("100%".to_owned()));
+        assert!(parse("%2e%2E") == Err(ApiError::BadName));
+        assert!(parse("a%2Fb") == Err(ApiError::BadName));
+        assert!(parse("%zz") == Err(ApiError::BadRequest));
+        assert!(parse("%2") == Err(ApiError::BadRequest));
+        assert!(parse("%C3%A9") == Err(ApiError::BadRequest));
+    }
+
+    #[test]
+    fn splits_paths() {
+        let parts = ["world".to_owned(), "level.dat".to_owned()];
+        assert!(parse_path("world/level.dat") == Ok(parts.to_vec()));
+        assert!(parse_path("/world//level.dat/") == Ok(parts.to_vec()));
+        assert!(parse_path("") == Ok(Vec::new()));
+        assert!(parse_path("world/../..") == Err(ApiError::BadName));
+        assert!(parse_path("world/%2E%2E") == Err(ApiError::BadName));
+    }
+}
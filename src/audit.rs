@@ -0,0 +1,116 @@
+use crate::auth::User;
+use crate::utils::{now, time_in_range, ApiError};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, ErrorKind, Write};
+
+/// kept next to mc-manager.properties, one json object per line, oldest first
+const AUDIT_FILE: &str = "mc-manager-audit.log";
+
+/// the most entries a single query returns
+const MAX_QUERY_ENTRIES: usize = 1000;
+
+/// serializes the writes, so lines from concurrent requests are never interleaved
+static WRITE_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// a mutating api call, recorded whether it succeeded or not
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// in the format of `utils::now`, so entries can be compared as text
+    time: String,
+    user: String,
+    /// the address of the client, if warp knows it
+    ip: Option<String>,
+    /// the name of the api
+    action: String,
+    save: Option<String>,
+    /// extra information about the call, like the command sent or the backup restored
+    detail: Option<String>,
+    /// "ok" or the code of the error, as in the `err` field of error responses
+    outcome: String,
+}
+
+/// the filters of `query`, all optional
+#[derive(Deserialize)]
+pub struct Filter {
+    save: Option<String>,
+    /// entries at or after this time, in the format of `utils::now` or a prefix of it, like 2024-01-31
+    from: Option<String>,
+    /// entries at or before this time, a prefix includes everything it matches, like a whole day
+    to: Option<String>,
+    /// how many of the newest entries to return
+    limit: Option<usize>,
+}
+
+/// appends an entry to the audit log, failing to write it is reported but does not fail the call
+pub fn record<T>(
+    user: &User,
+    action: &str,
+    save: Option<&str>,
+    detail: Option<&str>,
+    result: &Result<T, ApiError>,
+) {
+    let entry = Entry {
+        time: now(),
+        user: user.name.clone(),
+        ip: user.ip.map(|ip| ip.to_string()),
+        action: action.to_owned(),
+        save: save.map(str::to_owned),
+        detail: detail.map(str::to_owned),
+        outcome: match result {
+            Ok(_) => "ok".to_owned(),
+            Err(error) => error.code().to_owned(),
+        },
+    };
+    let mut line = serde_json::to_string(&entry).expect("audit entries are always serializable");
+    line.push('\n');
+    let _lock = WRITE_LOCK.lock().expect("WRITE_LOCK is poisoned");
+    let result = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(AUDIT_FILE)
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+    if let Err(error) = result {
+        println!("[!] Could not write to the audit log: {error}");
+    }
+}
+
+/// returns a valid json with the newest entries that match the filter, newest first
+pub fn query(filter: &Filter) -> Result<String, ApiError> {
+    let file = match std::fs::File::open(AUDIT_FILE) {
+        Ok(file) => file,
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            return Ok(r#"{"entries":[]}"#.to_owned())
+        }
+        Err(error) => return Err(error.into()),
+    };
+    let limit = filter.limit.unwrap_or(100).clamp(1, MAX_QUERY_ENTRIES);
+    let mut entries = std::collections::VecDeque::with_capacity(limit);
+    for line in std::io::BufReader::new(file).lines() {
+        // a line that was cut short by a crash is skipped
+        let Ok(entry) = serde_json::from_str::<Entry>(&line?) else {
+            continue;
+        };
+        if !matches(filter, &entry) {
+            continue;
+        }
+        if entries.len() == limit {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+    let entries: Vec<Entry> = entries.into_iter().rev().collect();
+    let mut out = String::with_capacity(256 * entries.len() + 16);
+    out += r#"{"entries":"#;
+    out += &serde_json::to_string(&entries).expect("audit entries are always serializable");
+    out += "}";
+    Ok(out)
+}
+
+fn matches(filter: &Filter, entry: &Entry) -> bool {
+    if let Some(save) = &filter.save {
+        if entry.save.as_ref() != Some(save) {
+            return false;
+        }
+    }
+    time_in_range(&entry.time, filter.from.as_deref(), filter.to.as_deref())
+}
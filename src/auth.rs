@@ -0,0 +1,413 @@
+use crate::utils::{append_comma_separated, append_json_string, ApiError};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+use warp::reject::Reject;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+lazy_static! {
+    static ref USERS: std::sync::RwLock<Vec<Account>> = std::sync::RwLock::new(Vec::new());
+    static ref SESSIONS: std::sync::Mutex<HashMap<String, Session>> = std::sync::Mutex::new(HashMap::new());
+}
+
+/// kept next to mc-manager.properties
+const USERS_FILE: &str = "mc-manager-users.json";
+
+pub const SESSION_COOKIE: &str = "mc-manager-session";
+
+/// how long a session lasts after logging in
+const SESSION_DURATION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// a hash made with the default parameters, checked against when the user does not exist
+const DUMMY_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$VJ0GWgn+IalLhguEbuu+6w$F6vL4w30FE5iTSSX3oHlXwp7c4x7Jz6A/7j7U8AV09Q";
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Account {
+    name: String,
+    /// the argon2 hash of the password, in the PHC string format
+    password: String,
+    /// accounts created before roles existed are admins
+    #[serde(default = "Role::admin")]
+    role: Role,
+    /// overrides the access given by the role for specific saves
+    #[serde(default)]
+    grants: BTreeMap<String, Access>,
+}
+
+/// decides the access a user has to saves without a grant, and what else they can do
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// can do anything, including managing users, grants do not apply to admins
+    Admin,
+    /// can create saves, and start and stop any save
+    Operator,
+    /// can see any save and its console
+    Viewer,
+}
+
+/// what a user can do with a save, each level can also do everything the levels below it can
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    /// the save is hidden from the user
+    None,
+    /// can see the save, its status, console and backups
+    View,
+    /// can start and stop the save and make backups
+    Operate,
+    /// can modify, delete, restore backups, schedule and run console commands
+    Manage,
+}
+
+/// the user that made a request, extracted by `authenticated`
+#[derive(Clone)]
+pub struct User {
+    pub name: String,
+    /// the address the request came from, for the audit log
+    pub ip: Option<IpAddr>,
+    role: Role,
+    grants: BTreeMap<String, Access>,
+}
+
+impl Role {
+    fn admin() -> Self {
+        Role::Admin
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Operator => "operator",
+            Role::Viewer => "viewer",
+        }
+    }
+    /// the access to saves that are not in the grants
+    fn default_access(self) -> Access {
+        match self {
+            Role::Admin => Access::Manage,
+            Role::Operator => Access::Operate,
+            Role::Viewer => Access::View,
+        }
+    }
+}
+
+impl User {
+    pub fn role(&self) -> Role {
+        self.role
+    }
+    /// the access the user has to the save
+    pub fn access(&self, save: &str) -> Access {
+        if self.role == Role::Admin {
+            return Access::Manage;
+        }
+        match self.grants.get(save) {
+            Some(access) => *access,
+            None => self.role.default_access(),
+        }
+    }
+    /// returns Forbidden if the user does not have at least `access` to the save
+    pub fn require(&self, save: &str, access: Access) -> Result<(), ApiError> {
+        if self.access(save) >= access {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden)
+        }
+    }
+    /// returns Forbidden if the user can not create saves
+    pub fn require_create(&self) -> Result<(), ApiError> {
+        match self.role {
+            Role::Admin | Role::Operator => Ok(()),
+            Role::Viewer => Err(ApiError::Forbidden),
+        }
+    }
+    /// returns Forbidden if the user is not an admin
+    pub fn require_admin(&self) -> Result<(), ApiError> {
+        match self.role {
+            Role::Admin => Ok(()),
+            Role::Operator | Role::Viewer => Err(ApiError::Forbidden),
+        }
+    }
+}
+
+struct Session {
+    user: String,
+    expires: Instant,
+}
+
+/// the rejection used by `authenticated`, turned into `ApiError::Unauthorized` by `handle_rejection`
+#[derive(Debug)]
+struct Unauthorized;
+
+impl Reject for Unauthorized {}
+
+/// reads the users file, if it exists, must be called before serving
+pub fn load() -> Result<(), String> {
+    let users: Vec<Account> = match std::fs::read_to_string(USERS_FILE) {
+        Ok(text) => serde_json::from_str(&text).map_err(|error| error.to_string())?,
+        Err(error) if error.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(error) => return Err(error.to_string()),
+    };
+    *USERS.write().expect("USERS lock is poisoned") = users;
+    Ok(())
+}
+
+/// true if at least one user exists, without users no one can log in
+pub fn has_users() -> bool {
+    !USERS.read().expect("USERS lock is poisoned").is_empty()
+}
+
+/// returns a valid json with all users, their roles and grants
+pub fn list_users() -> String {
+    let users = USERS.read().expect("USERS lock is poisoned");
+    let mut out = String::with_capacity(1024);
+    out += r#"{"users":["#;
+    append_comma_separated(users.iter(), &mut out, |out, user| {
+        *out += r#"{"name":"#;
+        append_json_string(out, &user.name);
+        *out += r#","role":"#;
+        append_json_string(out, user.role.name());
+        *out += r#","grants":"#;
+        *out += &serde_json::to_string(&user.grants).expect("grants are always serializable");
+        *out += "}";
+    });
+    out += "]}";
+    out
+}
+
+/// creates a new user
+pub fn add_user(name: &str, password: &str, role: Role) -> Result<(), ApiError> {
+    validate_user(name, password)?;
+    let mut users = USERS.write().expect("USERS lock is poisoned");
+    if users.iter().any(|x| x.name == name) {
+        return Err(ApiError::UserAlreadyExists);
+    }
+    users.push(Account {
+        name: name.to_owned(),
+        password: hash_password(password)?,
+        role,
+        grants: BTreeMap::new(),
+    });
+    write(&users)
+}
+
+/// changes the role of a user, the last admin can not stop being one
+pub fn set_role(name: &str, role: Role) -> Result<(), ApiError> {
+    let mut users = USERS.write().expect("USERS lock is poisoned");
+    let Some(index) = users.iter().position(|x| x.name == name) else {
+        return Err(ApiError::UserNotFound);
+    };
+    if role != Role::Admin && is_last_admin(&users, index) {
+        return Err(ApiError::LastAdmin);
+    }
+    users[index].role = role;
+    write(&users)
+}
+
+/// sets the access of a user to a save, `None` removes the grant so the role decides
+pub fn set_grant(name: &str, save: &str, access: Option<Access>) -> Result<(), ApiError> {
+    let mut users = USERS.write().expect("USERS lock is poisoned");
+    let Some(user) = users.iter_mut().find(|x| x.name == name) else {
+        return Err(ApiError::UserNotFound);
+    };
+    match access {
+        Some(access) => user.grants.insert(save.to_owned(), access),
+        None => user.grants.remove(save),
+    };
+    write(&users)
+}
+
+/// removes the grants of every user to a save, used when the save is deleted
+pub fn remove_grants(save: &str) -> Result<(), ApiError> {
+    let mut users = USERS.write().expect("USERS lock is poisoned");
+    let mut changed = false;
+    for user in users.iter_mut() {
+        changed |= user.grants.remove(save).is_some();
+    }
+    if changed {
+        write(&users)?;
+    }
+    Ok(())
+}
+
+/// deletes a user and ends all of their sessions, the last admin can not be deleted
+pub fn remove_user(name: &str) -> Result<(), ApiError> {
+    let mut users = USERS.write().expect("USERS lock is poisoned");
+    let Some(index) = users.iter().position(|x| x.name == name) else {
+        return Err(ApiError::UserNotFound);
+    };
+    if is_last_admin(&users, index) {
+        return Err(ApiError::LastAdmin);
+    }
+    users.remove(index);
+    write(&users)?;
+    end_sessions(name);
+    Ok(())
+}
+
+/// changes the password of a user and ends all of their sessions
+pub fn set_password(name: &str, password: &str) -> Result<(), ApiError> {
+    validate_user(name, password)?;
+    let mut users = USERS.write().expect("USERS lock is poisoned");
+    let Some(user) = users.iter_mut().find(|x| x.name == name) else {
+        return Err(ApiError::UserNotFound);
+    };
+    user.password = hash_password(password)?;
+    write(&users)?;
+    end_sessions(name);
+    Ok(())
+}
+
+/// checks the password of the user
+pub fn verify_password(name: &str, password: &str) -> bool {
+    let users = USERS.read().expect("USERS lock is poisoned");
+    let user = users.iter().find(|x| x.name == name);
+    // a missing user takes as long as a wrong password, so the time does not tell which users exist
+    let hash = user.map_or(DUMMY_HASH, |user| user.password.as_str());
+    match PasswordHash::new(hash) {
+        Ok(hash) => {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+                && user.is_some()
+        }
+        Err(_) => false,
+    }
+}
+
+/// checks the password and creates a new session, returns its token
+pub fn login(name: &str, password: &str) -> Result<String, ApiError> {
+    if !verify_password(name, password) {
+        return Err(ApiError::BadCredentials);
+    }
+    let mut bytes = [0; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let mut token = String::with_capacity(64);
+    for byte in bytes {
+        token += &format!("{byte:02x}");
+    }
+    let mut sessions = SESSIONS.lock().expect("SESSIONS lock is poisoned");
+    let now = Instant::now();
+    sessions.retain(|_, session| session.expires > now);
+    sessions.insert(
+        token.clone(),
+        Session {
+            user: name.to_owned(),
+            expires: now + SESSION_DURATION,
+        },
+    );
+    Ok(token)
+}
+
+/// ends the session of the token
+pub fn logout(token: &str) {
+    SESSIONS
+        .lock()
+        .expect("SESSIONS lock is poisoned")
+        .remove(token);
+}
+
+/// returns the user the token belongs to, if the session is still valid
+pub fn session_user(token: &str) -> Option<User> {
+    let name = {
+        let sessions = SESSIONS.lock().expect("SESSIONS lock is poisoned");
+        sessions
+            .get(token)
+            .filter(|session| session.expires > Instant::now())
+            .map(|session| session.user.clone())?
+    };
+    let users = USERS.read().expect("USERS lock is poisoned");
+    users.iter().find(|x| x.name == name).map(|account| User {
+        name,
+        ip: None,
+        role: account.role,
+        grants: account.grants.clone(),
+    })
+}
+
+/// extracts the session token from the `Authorization: Bearer` header or from the session cookie
+pub fn token() -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::cookie::optional::<String>(SESSION_COOKIE))
+        .map(|header: Option<String>, cookie: Option<String>| {
+            header
+                .as_deref()
+                .and_then(|x| x.strip_prefix("Bearer "))
+                .map(|x| x.trim().to_owned())
+                .or(cookie)
+        })
+}
+
+/// rejects requests that do not have a valid session, extracts the user that made the request
+pub fn authenticated() -> impl Filter<Extract = (User,), Error = Rejection> + Clone {
+    token().and(warp::addr::remote()).and_then(
+        |token: Option<String>, address: Option<SocketAddr>| async move {
+            match token.as_deref().and_then(session_user) {
+                Some(user) => Ok(User {
+                    ip: address.map(|x| x.ip()),
+                    ..user
+                }),
+                None => Err(warp::reject::custom(Unauthorized)),
+            }
+        },
+    )
+}
+
+/// turns the rejection from `authenticated` into a proper response
+pub async fn handle_rejection(rejection: Rejection) -> Result<Response, Rejection> {
+    if rejection.find::<Unauthorized>().is_some() {
+        Ok(ApiError::Unauthorized.into_response())
+    } else {
+        Err(rejection)
+    }
+}
+
+fn validate_user(name: &str, password: &str) -> Result<(), ApiError> {
+    if name.is_empty()
+        || name.len() > 64
+        || !name
+            .bytes()
+            .all(|x| x.is_ascii_alphanumeric() || matches!(x, b'-' | b'_' | b'.'))
+    {
+        return Err(ApiError::BadName);
+    }
+    if password.len() < 8 {
+        return Err(ApiError::WeakPassword);
+    }
+    Ok(())
+}
+
+/// without an admin no one could manage the users and the saves
+fn is_last_admin(users: &[Account], index: usize) -> bool {
+    users[index].role == Role::Admin && users.iter().filter(|x| x.role == Role::Admin).count() == 1
+}
+
+fn hash_password(password: &str) -> Result<String, ApiError> {
+    let salt = SaltString::generate(&mut OsRng);
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(error) => Err(ApiError::IOError(error.to_string())),
+    }
+}
+
+fn end_sessions(name: &str) {
+    SESSIONS
+        .lock()
+        .expect("SESSIONS lock is poisoned")
+        .retain(|_, session| session.user != name);
+}
+
+fn write(users: &[Account]) -> Result<(), ApiError> {
+    let text = serde_json::to_string_pretty(users).expect("users are always serializable");
+    let temp = format!("{USERS_FILE}.tmp");
+    std::fs::write(&temp, text)?;
+    std::fs::rename(&temp, USERS_FILE)?;
+    Ok(())
+}
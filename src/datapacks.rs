@@ -0,0 +1,199 @@
+use crate::properties::read_property;
+use crate::state::save;
+use crate::utils::{append_comma_separated, append_json_string, ApiError};
+use std::fs::File;
+use std::io::{Cursor, ErrorKind, Read};
+use std::path::Path;
+use zip::ZipArchive;
+
+/// the biggest zip that can be uploaded
+pub const MAX_DATAPACK_SIZE: u64 = 64 * 1024 * 1024;
+
+/// the biggest pack.mcmeta read from a pack
+const MAX_MCMETA_SIZE: u64 = 64 * 1024;
+
+/// what the pack.mcmeta of a pack says about it, the fields it does not have are none
+#[derive(Default)]
+struct PackInfo {
+    description: Option<String>,
+    format: Option<u64>,
+}
+
+/// returns a valid json with the datapacks installed in the world of the save
+///
+/// packs can be zips or folders, their id in the `datapack` command is `file/` followed by the name
+pub fn list(save: &str) -> Result<String, ApiError> {
+    let dir = dir(save)?;
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        // created by the server when it first starts
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            return Ok(r#"{"datapacks":[]}"#.to_owned())
+        }
+        Err(error) => return Err(error.into()),
+    };
+    let mut packs = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let metadata = entry.metadata()?;
+        let info = if metadata.is_dir() {
+            read_file(&entry.path().join("pack.mcmeta"))
+        } else if name.ends_with(".zip") {
+            read_zip(&entry.path())
+        } else {
+            continue;
+        };
+        let folder = metadata.is_dir();
+        // a pack that can not be read is still listed, so it can be removed
+        packs.push((name, folder, metadata.len(), info.unwrap_or_default()));
+    }
+    packs.sort_by_key(|pack| pack.0.to_lowercase());
+    let mut out = String::with_capacity(256 * packs.len() + 16);
+    out += r#"{"datapacks":["#;
+    append_comma_separated(packs.iter(), &mut out, |out, (name, folder, size, info)| {
+        *out += r#"{"name":"#;
+        append_json_string(out, name);
+        *out += r#","id":"#;
+        append_json_string(out, &format!("file/{name}"));
+        *out += r#","folder":"#;
+        *out += if *folder { "true" } else { "false" };
+        if !folder {
+            *out += r#","size":"#;
+            *out += &size.to_string();
+        }
+        *out += r#","description":"#;
+        match &info.description {
+            Some(description) => append_json_string(out, description),
+            None => *out += "null",
+        }
+        *out += r#","format":"#;
+        match info.format {
+            Some(format) => *out += &format.to_string(),
+            None => *out += "null",
+        }
+        *out += "}";
+    });
+    out += "]}";
+    Ok(out)
+}
+
+/// adds a zipped pack to the world, replacing the one with the same name
+pub fn upload(save: &str, file: &str, data: &[u8]) -> Result<(), ApiError> {
+    let dir = dir(save)?;
+    if !file.ends_with(".zip") {
+        return Err(ApiError::DatapackInvalid);
+    }
+    // the server ignores zips without a pack.mcmeta at the root
+    let valid = ZipArchive::new(Cursor::new(data))
+        .is_ok_and(|mut archive| archive.by_name("pack.mcmeta").is_ok());
+    if !valid {
+        return Err(ApiError::DatapackInvalid);
+    }
+    std::fs::create_dir_all(&dir)?;
+    let path = format!("{dir}/{file}");
+    let temp = format!("{path}.part");
+    std::fs::write(&temp, data)?;
+    if let Err(error) = std::fs::rename(&temp, &path) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(error.into());
+    }
+    Ok(())
+}
+
+/// removes a pack, zipped or a folder
+pub fn delete(save: &str, name: &str) -> Result<(), ApiError> {
+    let path = format!("{}/{name}", dir(save)?);
+    let result = match std::fs::symlink_metadata(&path) {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(&path),
+        Ok(_) => std::fs::remove_file(&path),
+        Err(error) => Err(error),
+    };
+    result.map_err(|error| match error.kind() {
+        ErrorKind::NotFound => ApiError::DatapackNotFound,
+        _ => error.into(),
+    })
+}
+
+/// fails with DatapackNotFound if the pack is not installed
+pub fn exists(save: &str, name: &str) -> Result<(), ApiError> {
+    match std::fs::symlink_metadata(format!("{}/{name}", dir(save)?)) {
+        Ok(_) => Ok(()),
+        Err(error) if error.kind() == ErrorKind::NotFound => Err(ApiError::DatapackNotFound),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// the datapacks folder of the world in `level-name`
+fn dir(save: &str) -> Result<String, ApiError> {
+    save::exists(save)?;
+    let level = read_property(format!("saves/{save}/server.properties"), "level-name")?;
+    let level = level.as_deref().map(str::trim).unwrap_or("world");
+    let level = if level.is_empty() { "world" } else { level };
+    if level.starts_with('.') || level.contains(['/', '\\', ':']) {
+        return Err(ApiError::BadConfig("level-name".to_owned()));
+    }
+    Ok(format!("saves/{save}/{level}/datapacks"))
+}
+
+fn read_file(path: &Path) -> Option<PackInfo> {
+    let mut text = String::new();
+    File::open(path)
+        .ok()?
+        .take(MAX_MCMETA_SIZE)
+        .read_to_string(&mut text)
+        .ok()?;
+    parse_mcmeta(&text)
+}
+
+fn read_zip(path: &Path) -> Option<PackInfo> {
+    let mut archive = ZipArchive::new(File::open(path).ok()?).ok()?;
+    let mut text = String::new();
+    archive
+        .by_name("pack.mcmeta")
+        .ok()?
+        .take(MAX_MCMETA_SIZE)
+        .read_to_string(&mut text)
+        .ok()?;
+    parse_mcmeta(&text)
+}
+
+/// like `{"pack":{"pack_format":26,"description":"..."}}`
+fn parse_mcmeta(text: &str) -> Option<PackInfo> {
+    // some packs are saved with a byte order mark
+    let json: serde_json::Value = serde_json::from_str(text.trim_start_matches('\u{feff}')).ok()?;
+    let pack = json.get("pack")?;
+    let mut description = String::new();
+    if let Some(value) = pack.get("description") {
+        append_text(&mut description, value);
+    }
+    Some(PackInfo {
+        description: Some(description).filter(|x| !x.is_empty()),
+        format: pack.get("pack_format").and_then(|x| x.as_u64()),
+    })
+}
+
+/// the description can be a text component, only its plain text is kept
+fn append_text(out: &mut String, value: &serde_json::Value) {
+    match value {
+        serde_json::Value::String(text) => out.push_str(text),
+        serde_json::Value::Array(values) => {
+            for value in values {
+                append_text(out, value);
+            }
+        }
+        serde_json::Value::Object(object) => {
+            if let Some(text) = object.get("text") {
+                append_text(out, text);
+            }
+            if let Some(extra) = object.get("extra") {
+                append_text(out, extra);
+            }
+        }
+        serde_json::Value::Number(number) => out.push_str(&number.to_string()),
+        serde_json::Value::Bool(value) => out.push_str(if *value { "true" } else { "false" }),
+        serde_json::Value::Null => {}
+    }
+}
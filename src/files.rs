@@ -0,0 +1,131 @@
+use crate::state::save;
+use crate::utils::{append_comma_separated, append_json_string, format_system_time, ApiError};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// the biggest file that can be uploaded
+pub const MAX_FILE_SIZE: u64 = 256 * 1024 * 1024;
+
+/// files at the root of the save that can only be changed through other apis,
+/// as editing them directly would skip their validation
+const PROTECTED: &[&str] = &["server.properties"];
+
+/// what is at a path of a save
+pub enum Entry {
+    /// a valid json with the contents of the directory
+    Directory(String),
+    File(tokio::fs::File, u64),
+}
+
+/// lists the directory or opens the file at `path`, the root of the save if it is empty
+///
+/// every part of the path must already have passed `api::is_safe`
+pub async fn read(save: &str, path: &[String]) -> Result<Entry, ApiError> {
+    let full = resolve(save, path)?;
+    let metadata = std::fs::metadata(&full).map_err(not_found)?;
+    if metadata.is_file() {
+        let file = tokio::fs::File::open(&full).await.map_err(not_found)?;
+        return Ok(Entry::File(file, metadata.len()));
+    }
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(&full).map_err(not_found)? {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let metadata = entry.metadata()?;
+        if metadata.file_type().is_symlink() {
+            continue;
+        }
+        entries.push((name, metadata));
+    }
+    entries.sort_by_key(|(name, metadata)| (!metadata.is_dir(), name.to_lowercase()));
+    let mut out = String::with_capacity(128 * entries.len() + 64);
+    out += r#"{"path":"#;
+    append_json_string(&mut out, &path.join("/"));
+    out += r#","entries":["#;
+    append_comma_separated(entries.iter(), &mut out, |out, (name, metadata)| {
+        *out += r#"{"name":"#;
+        append_json_string(out, name);
+        *out += r#","dir":"#;
+        *out += if metadata.is_dir() { "true" } else { "false" };
+        if !metadata.is_dir() {
+            *out += r#","size":"#;
+            *out += &metadata.len().to_string();
+        }
+        if let Ok(modified) = metadata.modified() {
+            *out += r#","modified":"#;
+            append_json_string(out, &format_system_time(modified));
+        }
+        *out += "}";
+    });
+    out += "]}";
+    Ok(Entry::Directory(out))
+}
+
+/// creates or replaces the file at `path`, creating the directories it is in
+pub fn write(save: &str, path: &[String], data: &[u8]) -> Result<(), ApiError> {
+    let full = resolve_writable(save, path)?;
+    if full.is_dir() {
+        return Err(ApiError::BadRequest);
+    }
+    if let Some(parent) = full.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut temp = full.clone().into_os_string();
+    temp.push(".part");
+    std::fs::write(&temp, data)?;
+    if let Err(error) = std::fs::rename(&temp, &full) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(error.into());
+    }
+    Ok(())
+}
+
+/// removes the file or the directory and everything in it at `path`
+pub fn delete(save: &str, path: &[String]) -> Result<(), ApiError> {
+    let full = resolve_writable(save, path)?;
+    let metadata = std::fs::metadata(&full).map_err(not_found)?;
+    let result = if metadata.is_dir() {
+        std::fs::remove_dir_all(&full)
+    } else {
+        std::fs::remove_file(&full)
+    };
+    result.map_err(not_found)
+}
+
+/// like `resolve`, but the root of the save and the protected files are refused
+fn resolve_writable(save: &str, path: &[String]) -> Result<PathBuf, ApiError> {
+    match path {
+        [] => Err(ApiError::BadRequest),
+        [name] if PROTECTED.iter().any(|x| name.eq_ignore_ascii_case(x)) => {
+            Err(ApiError::Forbidden)
+        }
+        _ => resolve(save, path),
+    }
+}
+
+/// the path inside the folder of the save, none of its parts can be a symbolic link,
+/// as it could point outside of the save
+fn resolve(save: &str, path: &[String]) -> Result<PathBuf, ApiError> {
+    save::exists(save)?;
+    let mut full = PathBuf::from(format!("saves/{save}"));
+    for part in path {
+        full.push(part);
+        match std::fs::symlink_metadata(&full) {
+            Ok(metadata) if metadata.file_type().is_symlink() => return Err(ApiError::Forbidden),
+            Ok(_) => {}
+            // the rest of the path does not exist either
+            Err(error) if error.kind() == ErrorKind::NotFound => {}
+            Err(error) => return Err(error.into()),
+        }
+    }
+    Ok(full)
+}
+
+fn not_found(error: std::io::Error) -> ApiError {
+    match error.kind() {
+        ErrorKind::NotFound => ApiError::FileNotFound,
+        _ => error.into(),
+    }
+}
@@ -0,0 +1,445 @@
+use crate::instances::InstanceStatus;
+use crate::properties::{generate_properties, read_properties, write_properties, PropValue};
+use crate::state::save;
+use crate::utils::ApiError;
+use flate2::read::GzDecoder;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::path::{Component, PathBuf};
+use zip::ZipArchive;
+
+/// the biggest zip that can be uploaded
+pub const MAX_IMPORT_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// the most bytes the files in the zip can add up to once extracted
+const MAX_EXTRACTED_SIZE: u64 = 16 * 1024 * 1024 * 1024;
+
+/// the most bytes read from level.dat once decompressed
+const MAX_LEVEL_SIZE: u64 = 64 * 1024 * 1024;
+
+/// how deep the tags of level.dat can be nested
+const MAX_NBT_DEPTH: usize = 512;
+
+/// where the save is inside an uploaded zip
+pub struct Layout {
+    /// the folder in the zip that becomes the save folder, or its world folder
+    root: Vec<String>,
+    /// true for a singleplayer world, which is placed in the world folder of the save,
+    /// false for a server folder, which already has its world
+    pub world: bool,
+    /// the version that last opened the world, from its level.dat
+    pub version: Option<String>,
+}
+
+/// finds out if the zip has a server folder or a singleplayer world, and the version of the world
+///
+/// a server folder has a server.properties, a world has a level.dat,
+/// the ones closest to the root of the zip are used, as they may also be inside another folder
+pub fn inspect(data: &[u8]) -> Result<Layout, ApiError> {
+    let mut archive = ZipArchive::new(Cursor::new(data)).map_err(|_| ApiError::ImportInvalid)?;
+    let mut properties: Option<(usize, Vec<String>)> = None;
+    let mut levels: Vec<(usize, Vec<String>)> = Vec::new();
+    // the folders with plugins or mods, which the vanilla server would ignore
+    let mut loaded: Vec<Vec<String>> = Vec::new();
+    let mut total: u64 = 0;
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index)?;
+        total = total.saturating_add(entry.size());
+        if entry.is_dir() {
+            continue;
+        }
+        let Some(path) = entry_path(entry.enclosed_name()) else {
+            continue;
+        };
+        let Some((file, dir)) = path.split_last() else {
+            continue;
+        };
+        if file == "server.properties" && properties.as_ref().is_none_or(|x| x.1.len() > dir.len())
+        {
+            properties = Some((index, dir.to_vec()));
+        } else if file == "level.dat" {
+            levels.push((index, dir.to_vec()));
+        } else if file.ends_with(".jar")
+            && dir.last().is_some_and(|x| x == "plugins" || x == "mods")
+        {
+            loaded.push(dir.to_vec());
+        }
+    }
+    if total > MAX_EXTRACTED_SIZE {
+        return Err(ApiError::ImportInvalid);
+    }
+    levels.sort_by_key(|level| level.1.len());
+    let (root, world, level) = match properties {
+        Some((index, root)) => {
+            // the world of a server is the folder in level-name
+            let mut text = String::new();
+            archive
+                .by_index(index)?
+                .take(64 * 1024)
+                .read_to_string(&mut text)?;
+            let level_name = text
+                .lines()
+                .filter(|line| !line.starts_with('#'))
+                .filter_map(|line| line.split_once('='))
+                .find(|(key, _)| key.trim() == "level-name")
+                .map_or("world".to_owned(), |(_, value)| value.trim().to_owned());
+            let level = levels
+                .iter()
+                .find(|level| {
+                    level.1.len() == root.len() + 1
+                        && level.1.starts_with(&root)
+                        && level.1[root.len()] == level_name
+                })
+                .or_else(|| levels.iter().find(|level| level.1.starts_with(&root)))
+                .map(|level| level.0);
+            // only vanilla servers can be imported, a server with plugins or mods would lose them
+            if loaded
+                .iter()
+                .any(|dir| dir.len() == root.len() + 1 && dir.starts_with(&root))
+            {
+                return Err(ApiError::FlavorNotSupported);
+            }
+            (root, false, level)
+        }
+        None => match levels.first() {
+            Some((index, root)) => (root.clone(), true, Some(*index)),
+            None => return Err(ApiError::ImportInvalid),
+        },
+    };
+    let version = match level {
+        Some(index) => {
+            let mut decoded = Vec::new();
+            GzDecoder::new(archive.by_index(index)?)
+                .take(MAX_LEVEL_SIZE)
+                .read_to_end(&mut decoded)
+                .ok()
+                .and_then(|_| level_version(&decoded))
+        }
+        None => None,
+    };
+    Ok(Layout {
+        root,
+        world,
+        version,
+    })
+}
+
+/// creates the save from the zip, with the vanilla server of `version`, which must be downloaded,
+/// returns the same as `save::load`
+///
+/// the properties of an imported server are kept, except the ones of mc-manager
+pub fn create(name: &str, data: &[u8], layout: &Layout, version: &str) -> Result<String, ApiError> {
+    match save::exists(name) {
+        Err(ApiError::NotFound) => {}
+        Err(error) => return Err(error),
+        Ok(()) => return Err(ApiError::AlreadyExists),
+    }
+    if std::fs::metadata(format!("versions/{version}.jar")).is_err() {
+        return Err(ApiError::VersionNotFound);
+    }
+    let _lock = save::lock(name)?;
+    std::fs::create_dir(format!("saves/{name}"))?;
+    if let Err(error) = extract(name, data, layout, version) {
+        std::fs::remove_dir_all(format!("saves/{name}"))?;
+        return Err(error);
+    }
+    save::load(name, InstanceStatus::Offline)
+}
+
+fn extract(name: &str, data: &[u8], layout: &Layout, version: &str) -> Result<(), ApiError> {
+    let dir = PathBuf::from(format!("saves/{name}"));
+    let target = if layout.world {
+        dir.join("world")
+    } else {
+        dir.clone()
+    };
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+    // the sizes checked by `inspect` are the ones the zip claims, not the ones it extracts to
+    let mut remaining = MAX_EXTRACTED_SIZE;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index)?;
+        // links could point outside of the save
+        if entry.is_symlink() {
+            continue;
+        }
+        let Some(path) = entry_path(entry.enclosed_name()) else {
+            continue;
+        };
+        let Some(relative) = path.strip_prefix(layout.root.as_slice()) else {
+            continue;
+        };
+        if relative.is_empty() {
+            continue;
+        }
+        let destination = relative
+            .iter()
+            .fold(target.clone(), |path, part| path.join(part));
+        if entry.is_dir() {
+            std::fs::create_dir_all(&destination)?;
+            continue;
+        }
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::File::create(&destination)?;
+        let copied = std::io::copy(&mut (&mut entry).take(remaining + 1), &mut file)?;
+        remaining = remaining
+            .checked_sub(copied)
+            .ok_or(ApiError::ImportInvalid)?;
+    }
+    let properties = format!("saves/{name}/server.properties");
+    let imported = match std::fs::metadata(&properties) {
+        Ok(_) => read_properties(&properties)?,
+        Err(_) => HashMap::new(),
+    };
+    std::fs::write(&properties, generate_properties(version, &HashMap::new()))?;
+    // the properties of mc-manager are not trusted, as they are passed to java
+    let imported: HashMap<String, PropValue> = imported
+        .into_iter()
+        .filter(|(key, _)| !key.starts_with("mc-manager-"))
+        // the world must stay inside the save
+        .filter(|(key, value)| {
+            key != "level-name" || !(value.starts_with('.') || value.contains(['/', '\\', ':']))
+        })
+        .map(|(key, value)| (key, PropValue::String(value)))
+        .collect();
+    write_properties(&properties, imported)?;
+    std::fs::write(
+        format!("saves/{name}/eula.txt"),
+        "# file created by mc-manager\r\neula=true\r\n",
+    )?;
+    let jar = format!("saves/{name}/server.jar");
+    if std::fs::metadata(&jar).is_ok() {
+        std::fs::remove_file(&jar)?;
+    }
+    crate::versions::install(version, &jar)?;
+    Ok(())
+}
+
+/// the parts of a path inside the zip, none if it is not a normal relative path
+fn entry_path(path: Option<PathBuf>) -> Option<Vec<String>> {
+    path?
+        .components()
+        .map(|component| match component {
+            Component::Normal(part) => part.to_str().map(str::to_owned),
+            _ => None,
+        })
+        .collect()
+}
+
+/// reads Data.Version.Name from the decompressed level.dat, worlds before 15w32a do not have it
+fn level_version(data: &[u8]) -> Option<String> {
+    let mut nbt = Nbt { data, offset: 0 };
+    // the root is a named compound
+    if nbt.u8()? != 10 {
+        return None;
+    }
+    nbt.string()?;
+    nbt.find(10, "Data")?;
+    nbt.find(10, "Version")?;
+    nbt.find(8, "Name")?;
+    nbt.string()
+}
+
+/// a reader of the binary format of level.dat
+struct Nbt<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl Nbt<'_> {
+    fn take(&mut self, length: usize) -> Option<&[u8]> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset.checked_add(length)?)?;
+        self.offset += length;
+        Some(bytes)
+    }
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+    fn length(&mut self, size: usize) -> Option<usize> {
+        let bytes = self.take(size)?;
+        let length = bytes
+            .iter()
+            .fold(0u64, |length, byte| (length << 8) | *byte as u64);
+        // lengths are signed, a negative one is as good as empty
+        if size == 4 && length >= 1 << 31 {
+            return Some(0);
+        }
+        usize::try_from(length).ok()
+    }
+    fn string(&mut self) -> Option<String> {
+        let length = self.length(2)?;
+        // modified utf-8, which is the same for the characters of version names
+        Some(String::from_utf8_lossy(self.take(length)?).into_owned())
+    }
+    /// moves into the payload of the tag of the compound it is in with that type and name
+    fn find(&mut self, ty: u8, name: &str) -> Option<()> {
+        loop {
+            let tag = self.u8()?;
+            if tag == 0 {
+                return None;
+            }
+            let tag_name = self.string()?;
+            if tag == ty && tag_name == name {
+                return Some(());
+            }
+            self.skip(tag, 0)?;
+        }
+    }
+    fn skip(&mut self, tag: u8, depth: usize) -> Option<()> {
+        if depth > MAX_NBT_DEPTH {
+            return None;
+        }
+        match tag {
+            1 => self.take(1).map(|_| ()),
+            2 => self.take(2).map(|_| ()),
+            3 | 5 => self.take(4).map(|_| ()),
+            4 | 6 => self.take(8).map(|_| ()),
+            7 => {
+                let length = self.length(4)?;
+                self.take(length).map(|_| ())
+            }
+            8 => self.string().map(|_| ()),
+            9 => {
+                let item = self.u8()?;
+                for _ in 0..self.length(4)? {
+                    self.skip(item, depth + 1)?;
+                }
+                Some(())
+            }
+            10 => loop {
+                let tag = self.u8()?;
+                if tag == 0 {
+                    return Some(());
+                }
+                self.string()?;
+                self.skip(tag, depth + 1)?;
+            },
+            11 => {
+                let length = self.length(4)?;
+                self.take(length.checked_mul(4)?).map(|_| ())
+            }
+            12 => {
+                let length = self.length(4)?;
+                self.take(length.checked_mul(8)?).map(|_| ())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a tag with its type and name, followed by its payload
+    fn tag(ty: u8, name: &str, payload: &[u8]) -> Vec<u8> {
+        let mut out = vec![ty];
+        out.extend_from_slice(&string(name));
+        out.extend_from_slice(payload);
+        out
+    }
+
+    fn string(text: &str) -> Vec<u8> {
+        let mut out = (text.len() as u16).to_be_bytes().to_vec();
+        out.extend_from_slice(text.as_bytes());
+        out
+    }
+
+    fn compound(tags: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = tags.concat();
+        out.push(0);
+        out
+    }
+
+    fn level(data: &[Vec<u8>]) -> Vec<u8> {
+        tag(10, "", &compound(&[tag(10, "Data", &compound(data))]))
+    }
+
+    fn version(name: &str) -> Vec<u8> {
+        let id = tag(3, "Id", &3700i32.to_be_bytes());
+        tag(
+            10,
+            "Version",
+            &compound(&[id, tag(8, "Name", &string(name))]),
+        )
+    }
+
+    /// a tag of each type, which must be skipped to get to the version
+    fn other_tags() -> Vec<Vec<u8>> {
+        let list = [
+            &[10][..],
+            &2i32.to_be_bytes(),
+            &compound(&[]),
+            &compound(&[]),
+        ]
+        .concat();
+        vec![
+            tag(1, "hardcore", &[0]),
+            tag(2, "short", &[0, 1]),
+            tag(3, "GameType", &0i32.to_be_bytes()),
+            tag(4, "RandomSeed", &0i64.to_be_bytes()),
+            tag(5, "float", &0f32.to_be_bytes()),
+            tag(6, "BorderSize", &0f64.to_be_bytes()),
+            tag(7, "bytes", &[&3i32.to_be_bytes()[..], &[1, 2, 3]].concat()),
+            tag(8, "LevelName", &string("world")),
+            tag(9, "ScheduledEvents", &list),
+            tag(9, "negative", &[&[1][..], &(-1i32).to_be_bytes()].concat()),
+            tag(
+                10,
+                "GameRules",
+                &compound(&[tag(8, "doDaylightCycle", &string("true"))]),
+            ),
+            tag(11, "ints", &[&1i32.to_be_bytes()[..], &[0; 4]].concat()),
+            tag(12, "longs", &[&1i32.to_be_bytes()[..], &[0; 8]].concat()),
+        ]
+    }
+
+    #[test]
+    fn reads_the_version() {
+        let mut data = other_tags();
+        data.push(version("1.20.4"));
+        assert_eq!(level_version(&level(&data)).as_deref(), Some("1.20.4"));
+        assert_eq!(
+            level_version(&level(&[version("24w14a")])).as_deref(),
+            Some("24w14a")
+        );
+    }
+
+    #[test]
+    fn worlds_without_a_version() {
+        assert_eq!(level_version(&level(&other_tags())), None);
+        assert_eq!(level_version(&level(&[])), None);
+        // the version must be a compound
+        let data = [tag(8, "Version", &string("1.20.4"))];
+        assert_eq!(level_version(&level(&data)), None);
+        // the root must be a compound
+        assert_eq!(level_version(&tag(9, "", &[0, 0, 0, 0, 0])), None);
+        assert_eq!(level_version(&[]), None);
+    }
+
+    #[test]
+    fn truncated_levels() {
+        let mut data = other_tags();
+        data.push(version("1.20.4"));
+        let level = level(&data);
+        for length in 0..level.len() - 3 {
+            assert_eq!(level_version(&level[..length]), None, "{length}");
+        }
+    }
+
+    #[test]
+    fn nested_too_deep() {
+        let nested = |depth| {
+            let mut list = [9, 0, 0, 0, 1].repeat(depth);
+            list.extend_from_slice(&[0, 0, 0, 0, 0]);
+            tag(9, "nested", &list)
+        };
+        let data = [nested(16), version("1.20.4")];
+        assert_eq!(level_version(&level(&data)).as_deref(), Some("1.20.4"));
+        let data = [nested(MAX_NBT_DEPTH + 1), version("1.20.4")];
+        assert_eq!(level_version(&level(&data)), None);
+    }
+}
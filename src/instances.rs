@@ -1,26 +1,68 @@
-use crate::properties::read_property;
+use crate::java;
+use crate::loaders::{self, Flavor};
+use crate::logs::LogWriter;
+use crate::properties::{read_properties, read_property, valid_string};
 use crate::server::is_shutdown;
 use crate::state::save;
-use crate::utils::{append_comma_separated, append_json_string, ApiError};
+use crate::utils::{append_comma_separated, append_json_string, now, ApiError, TIME_FORMAT};
+use futures::future::BoxFuture;
 use lazy_static::lazy_static;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc};
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::{ChildStdin, Command};
-use tokio::sync::{watch, Mutex, RwLock};
+use tokio::sync::{watch, Mutex, Notify, RwLock};
 
 lazy_static! {
     static ref INSTANCES: RwLock<HashMap<String, Instance>> = RwLock::new(HashMap::new());
 }
 
-static JAVA_PATH: std::sync::Mutex<String> = std::sync::Mutex::new(String::new());
+/// seconds to wait after a stop is issued before the java process is killed
+static STOP_TIMEOUT: AtomicU64 = AtomicU64::new(60);
+
+/// how long to wait for the server to confirm a `save-all flush`
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// how many lines of console output are kept in memory, older output is read from the logs
+const MAX_OUTPUT_LINES: usize = 1000;
+
+/// how many of the last lines of output are kept when the java process exits
+const EXIT_OUTPUT_LINES: usize = 20;
+
+/// how long the waiter waits for the readers to drain the pipes after the java process exits
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// written before every line the java process writes to stderr
+const STDERR_PREFIX: &[u8] = b"[stderr] ";
 
 struct Instance {
     status: InstanceStatus,
     port: u16,
     stdin: Arc<Mutex<ChildStdin>>,
     vector: Arc<InstanceVector>,
+    /// notified to make the waiter task kill the java process
+    kill: Arc<Notify>,
+    /// how many times the java process crashed since mc-manager started
+    crashes: u32,
+    /// how many automatic restarts happened since the server last finished loading
+    retries: u32,
+    /// set while waiting to be restarted automatically, clearing it cancels the restart
+    pending_restart: bool,
+    /// how the java process last exited, kept across restarts
+    last_exit: Option<Arc<ExitInfo>>,
+}
+
+/// how and when the java process exited, with the output that came right before it
+struct ExitInfo {
+    time: String,
+    /// none if the process was terminated by a signal
+    code: Option<i32>,
+    /// the signal that terminated the process, only known on unix
+    signal: Option<i32>,
+    lines: Vec<String>,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -35,10 +77,54 @@ pub enum InstanceStatus {
     Shutdown,
     /// set after shutdown is complete, there is console output
     Offline,
+    /// set when the java process exits with a non zero status without being stopped, there is console output
+    Crashed,
+}
+
+/// decides what happens when the java process exits without being stopped by mc-manager
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RestartPolicy {
+    Never,
+    OnFailure,
+    Always,
+}
+
+/// the restart settings of a save, read from its `mc-manager-restart-*` properties
+struct RestartSettings {
+    policy: RestartPolicy,
+    max_retries: u32,
+    backoff: u64,
+}
+
+/// how the java process of a save is launched, read from its `mc-manager-*` properties
+struct LaunchSettings {
+    /// the java executable, picked by `java::select`
+    java: String,
+    /// in megabytes, 0 for the default of the JVM
+    memory_min: u64,
+    memory_max: u64,
+    jvm_args: Vec<String>,
+    server_args: Vec<String>,
+    jar: String,
+    /// the `@` file with the classpath and main class, used by forge and neoforge instead of the jar
+    args_file: Option<String>,
 }
 
 pub struct InstanceVector {
-    sender: watch::Sender<(Vec<u8>, bool)>,
+    sender: watch::Sender<Output>,
+    /// also serializes the writes, so the offsets of the logs and of the output always match
+    log: std::sync::Mutex<LogWriter>,
+}
+
+/// the console output of a run of an instance, only the most recent lines are kept
+pub struct Output {
+    /// the offset of the first byte of `data`, counted from the start of the run
+    pub start: usize,
+    pub data: Vec<u8>,
+    /// false once the java process stopped writing
+    pub alive: bool,
+    /// the length of each line in `data`, oldest first
+    lines: VecDeque<usize>,
 }
 
 impl Instance {
@@ -48,14 +134,147 @@ impl Instance {
             InstanceStatus::Loading => Err(ApiError::BadInstanceStatus(InstanceStatus::Loading)),
             InstanceStatus::Online => {
                 let mut stdin = self.stdin.lock().await;
-                stdin.write(b"stop\r\n").await?;
+                stdin.write_all(b"stop\r\n").await?;
                 self.status = InstanceStatus::Shutdown;
+                self.schedule_kill();
                 Ok(())
             }
             InstanceStatus::Shutdown => Ok(()),
-            InstanceStatus::Offline => Err(ApiError::BadInstanceStatus(InstanceStatus::Offline)),
+            InstanceStatus::Offline | InstanceStatus::Crashed if self.pending_restart => {
+                self.pending_restart = false;
+                Ok(())
+            }
+            InstanceStatus::Offline | InstanceStatus::Crashed => Err(self.status.to_error()),
+        }
+    }
+    /// kills the java process immediatly, the exit is treated as if a stop was issued
+    fn kill(&mut self) -> Result<(), ApiError> {
+        match self.status {
+            InstanceStatus::Cold => unreachable!(),
+            InstanceStatus::Loading | InstanceStatus::Online | InstanceStatus::Shutdown => {
+                self.status = InstanceStatus::Shutdown;
+                self.kill.notify_one();
+                Ok(())
+            }
+            InstanceStatus::Offline | InstanceStatus::Crashed if self.pending_restart => {
+                self.pending_restart = false;
+                Ok(())
+            }
+            InstanceStatus::Offline | InstanceStatus::Crashed => Err(self.status.to_error()),
         }
     }
+    /// kills the java process if it is still running after the stop timeout
+    fn schedule_kill(&self) {
+        let kill = self.kill.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(get_stop_timeout()).await;
+            // if the process already exited no one is listening, and this does nothing
+            kill.notify_one();
+        });
+    }
+    fn is_running(&self) -> bool {
+        matches!(
+            self.status,
+            InstanceStatus::Loading | InstanceStatus::Online | InstanceStatus::Shutdown
+        )
+    }
+}
+
+impl RestartSettings {
+    fn read(name: &str) -> Result<Self, ApiError> {
+        let properties = read_properties(format!("saves/{name}/server.properties"))?;
+        let policy = match properties.get("mc-manager-restart-policy").map(|x| x.trim()) {
+            None | Some("never") => RestartPolicy::Never,
+            Some("on-failure") => RestartPolicy::OnFailure,
+            Some("always") => RestartPolicy::Always,
+            Some(_) => return Err(ApiError::BadConfig("mc-manager-restart-policy".to_owned())),
+        };
+        let max_retries = match properties.get("mc-manager-restart-max-retries") {
+            Some(value) => match value.trim().parse() {
+                Ok(value) => value,
+                Err(_) => return Err(ApiError::BadConfig("mc-manager-restart-max-retries".to_owned())),
+            },
+            None => 3,
+        };
+        let backoff = match properties.get("mc-manager-restart-backoff") {
+            Some(value) => match value.trim().parse() {
+                Ok(value) => value,
+                Err(_) => return Err(ApiError::BadConfig("mc-manager-restart-backoff".to_owned())),
+            },
+            None => 10,
+        };
+        Ok(RestartSettings {
+            policy,
+            max_retries,
+            backoff,
+        })
+    }
+    /// how long to wait before the restart that follows `retries` consecutive restarts
+    fn delay(&self, retries: u32) -> Duration {
+        Duration::from_secs(self.backoff.saturating_mul(1 << retries.min(16)))
+    }
+}
+
+impl LaunchSettings {
+    fn read(name: &str) -> Result<Self, ApiError> {
+        let properties = read_properties(format!("saves/{name}/server.properties"))?;
+        let memory = |prop: &str| match properties.get(prop) {
+            Some(value) => value
+                .trim()
+                .parse()
+                .map_err(|_| ApiError::BadConfig(prop.to_owned())),
+            None => Ok(0),
+        };
+        let memory_min = memory("mc-manager-memory-min")?;
+        let memory_max = memory("mc-manager-memory-max")?;
+        if memory_max != 0 && memory_min > memory_max {
+            return Err(ApiError::BadConfig("mc-manager-memory-min".to_owned()));
+        }
+        let string = |prop: &str, default: &str| match properties.get(prop) {
+            Some(value) if valid_string(prop, value.trim()) => Ok(value.trim().to_owned()),
+            Some(_) => Err(ApiError::BadConfig(prop.to_owned())),
+            None => Ok(default.to_owned()),
+        };
+        let split = |value: String| value.split_whitespace().map(str::to_owned).collect();
+        let version = string("mc-manager-server-version", "")?;
+        let java = java::select(&string("mc-manager-java", "")?, &version)?;
+        // saves created before flavors are vanilla
+        let flavor = string("mc-manager-server-flavor", "vanilla")?;
+        let flavor =
+            Flavor::parse(&flavor).ok_or_else(|| ApiError::BadConfig("mc-manager-server-flavor".to_owned()))?;
+        let build = string("mc-manager-server-build", "")?;
+        let args_file = loaders::args_file(&format!("saves/{name}"), flavor, &version, &build);
+        Ok(LaunchSettings {
+            java,
+            memory_min,
+            memory_max,
+            jvm_args: split(string("mc-manager-jvm-args", "")?),
+            server_args: split(string("mc-manager-server-args", "")?),
+            jar: string("mc-manager-server-jar", "server.jar")?,
+            args_file,
+        })
+    }
+    /// the arguments of the java command
+    fn args(&self) -> Vec<String> {
+        let mut args = Vec::with_capacity(self.jvm_args.len() + self.server_args.len() + 5);
+        if self.memory_min != 0 {
+            args.push(format!("-Xms{}M", self.memory_min));
+        }
+        if self.memory_max != 0 {
+            args.push(format!("-Xmx{}M", self.memory_max));
+        }
+        args.extend(self.jvm_args.iter().cloned());
+        match &self.args_file {
+            Some(file) => args.push(format!("@{file}")),
+            None => {
+                args.push("-jar".to_owned());
+                args.push(self.jar.clone());
+            }
+        }
+        args.push("nogui".to_owned());
+        args.extend(self.server_args.iter().cloned());
+        args
+    }
 }
 
 impl InstanceStatus {
@@ -65,24 +284,106 @@ impl InstanceStatus {
 }
 
 impl InstanceVector {
-    fn new() -> Self {
-        let (sender, _) = tokio::sync::watch::channel((Vec::new(), true));
-        InstanceVector { sender }
+    fn new(name: &str) -> Self {
+        let (sender, _) = tokio::sync::watch::channel(Output {
+            start: 0,
+            data: Vec::new(),
+            alive: true,
+            lines: VecDeque::new(),
+        });
+        InstanceVector {
+            sender,
+            log: std::sync::Mutex::new(LogWriter::open(name)),
+        }
     }
     async fn finish(&self) {
-        self.sender.send_modify(|(_, alive)| *alive = false);
+        self.sender.send_modify(|output| output.alive = false);
     }
-    async fn write(&self, data: &[u8]) {
-        self.sender
-            .send_modify(|(buffer, _)| buffer.extend_from_slice(data));
+    /// appends a line to the output and to the logs
+    async fn write(&self, line: &[u8]) {
+        let mut log = self.log.lock().expect("log lock is poisoned");
+        log.write(self.sender.borrow().end(), line);
+        self.sender.send_modify(|output| output.push(line));
     }
-    pub fn subscribe(&self) -> watch::Receiver<(Vec<u8>, bool)> {
+    pub fn subscribe(&self) -> watch::Receiver<Output> {
         self.sender.subscribe()
     }
+    /// reads output that is no longer in memory from the logs, see `LogWriter::read`
+    pub fn replay(&self, from: usize, to: usize) -> Option<(usize, Vec<u8>)> {
+        self.log
+            .lock()
+            .expect("log lock is poisoned")
+            .read(from, to)
+    }
+}
+
+impl Output {
+    /// the offset right after the last byte of output
+    pub fn end(&self) -> usize {
+        self.start + self.data.len()
+    }
+    /// the output from `offset` onwards, or all the output in memory if it starts after `offset`
+    pub fn since(&self, offset: usize) -> &[u8] {
+        let index = offset.saturating_sub(self.start).min(self.data.len());
+        &self.data[index..]
+    }
+    /// the last `count` lines in memory, oldest first
+    fn tail(&self, count: usize) -> Vec<String> {
+        let skip = self.lines.len().saturating_sub(count);
+        let mut offset: usize = self.lines.iter().take(skip).sum();
+        let mut out = Vec::with_capacity(count);
+        for length in self.lines.iter().skip(skip) {
+            let line = &self.data[offset..offset + length];
+            out.push(String::from_utf8_lossy(line).trim_end().to_owned());
+            offset += length;
+        }
+        out
+    }
+    fn push(&mut self, line: &[u8]) {
+        self.data.extend_from_slice(line);
+        self.lines.push_back(line.len());
+        // drops a batch of lines at once, so the data is not moved for every line
+        if self.lines.len() > MAX_OUTPUT_LINES + MAX_OUTPUT_LINES / 4 {
+            let mut dropped = 0;
+            while self.lines.len() > MAX_OUTPUT_LINES {
+                dropped += self.lines.pop_front().unwrap_or(0);
+            }
+            self.data.drain(..dropped);
+            self.start += dropped;
+        }
+    }
 }
 
 /// creates the instance, returns an error if it is already online
 pub async fn start_instance(name: &str) -> Result<(), ApiError> {
+    spawn_instance(name, 0).await
+}
+
+/// waits for the backoff and starts the instance again, unless the restart was cancelled in the meantime
+fn restart_instance(name: Arc<String>, retries: u32, delay: Duration) -> BoxFuture<'static, ()> {
+    Box::pin(async move {
+        tokio::time::sleep(delay).await;
+        match INSTANCES.write().await.get_mut(name.as_str()) {
+            Some(instance) if instance.pending_restart => instance.pending_restart = false,
+            _ => return,
+        }
+        if is_shutdown() {
+            return;
+        }
+        println!("[{name}] Restarting automatically, attempt {retries}");
+        if let Err(error) = spawn_instance(name.as_str(), retries).await {
+            match error {
+                ApiError::IOError(error) | ApiError::JavaError(error) => {
+                    println!("[!] Failed to restart save \"{name}\": {error}")
+                }
+                _ => println!("[!] Failed to restart save \"{name}\""),
+            }
+        }
+    })
+}
+
+/// creates the instance, `retries` is the number of consecutive automatic restarts that led to this one
+async fn spawn_instance(name: &str, retries: u32) -> Result<(), ApiError> {
     save::exists(name)?;
     let port = match read_property(format!("saves/{name}/server.properties"), "server-port")? {
         Some(port) => match port.parse() {
@@ -92,10 +393,17 @@ pub async fn start_instance(name: &str) -> Result<(), ApiError> {
         None => return Err(ApiError::BadConfig("server-port".to_owned())),
     };
     let mut instances = INSTANCES.write().await;
+    // checked while the instances are locked until this one is inserted as loading,
+    // so whoever locks the save and then sees the server stopped can not be raced by this start
+    save::check_busy(name)?;
+    let mut crashes = 0;
+    let mut last_exit = None;
     if let Some(instance) = instances.get(name) {
-        if instance.status != InstanceStatus::Offline {
+        if !matches!(instance.status, InstanceStatus::Offline | InstanceStatus::Crashed) {
             return Err(instance.status.to_error());
         }
+        crashes = instance.crashes;
+        last_exit = instance.last_exit.clone();
     }
     if instances.iter().any(|x| x.1.port == port && matches!(x.1.status, InstanceStatus::Loading | InstanceStatus::Online | InstanceStatus::Shutdown)) {
         return Err(ApiError::PortInUse);
@@ -113,12 +421,14 @@ pub async fn start_instance(name: &str) -> Result<(), ApiError> {
     if directory.starts_with(r"\\?\") {
         directory = &directory[4..];
     }
+    let launch = LaunchSettings::read(name)?;
     save::access(name)?;
-    let mut child = match Command::new(get_java_path().as_str())
-        .args(["-jar", "server.jar", "nogui"])
+    let mut child = match Command::new(&launch.java)
+        .args(launch.args())
         .current_dir(directory)
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
         .spawn()
     {
         Ok(child) => child,
@@ -130,48 +440,151 @@ pub async fn start_instance(name: &str) -> Result<(), ApiError> {
         return Err(ApiError::IOError("failed to capture process stdin".to_owned()));
     } else if child.stdout.is_none() {
         return Err(ApiError::IOError("failed to capture process stdout".to_owned()));
+    } else if child.stderr.is_none() {
+        return Err(ApiError::IOError("failed to capture process stderr".to_owned()));
     }
     let stdin = Arc::new(Mutex::new(child.stdin.take().unwrap()));
     let stdout = BufReader::new(child.stdout.take().unwrap());
-    let vector = Arc::new(InstanceVector::new());
+    let stderr = BufReader::new(child.stderr.take().unwrap());
+    let vector = Arc::new(InstanceVector::new(name));
+    let kill = Arc::new(Notify::new());
     let instance = Instance {
         status: InstanceStatus::Loading,
         port,
         stdin,
         vector: vector.clone(),
+        kill: kill.clone(),
+        crashes,
+        retries,
+        pending_restart: false,
+        last_exit,
     };
     let name_arc = Arc::new(name.to_owned());
     let name = name_arc.clone();
+    let waiter_vector = vector.clone();
     // waits for child to complete
     tokio::spawn(async move {
         println!("[{name}] Java process spawned");
-        match child.wait().await {
+        let status = tokio::select! {
+            status = child.wait() => status,
+            () = kill.notified() => {
+                println!("[{name}] Killing Java process");
+                if let Err(error) = child.start_kill() {
+                    println!("[{name}] An error occoured when attempting to kill Java process, {:?}", error);
+                }
+                child.wait().await
+            }
+        };
+        let failed = match &status {
             Ok(status) => match status.code() {
-                Some(0) => println!("[{name}] Java process finished"),
-                Some(status) => println!("[{name}] Java process finished with status {status}"),
-                None => println!("[{name}] Java process finished with no status"),
+                Some(0) => {
+                    println!("[{name}] Java process finished");
+                    false
+                }
+                Some(status) => {
+                    println!("[{name}] Java process finished with status {status}");
+                    true
+                }
+                None => {
+                    println!("[{name}] Java process finished with no status");
+                    true
+                }
             },
             Err(error) => {
                 println!(
                     "[{name}] An error occoured when attempting to wait for Java process, {:?}",
                     error
                 );
+                true
             }
-        }
+        };
+        // the last lines may still be in the pipes, unless some child of java keeps them open
+        let mut output = waiter_vector.subscribe();
+        let drained = async {
+            while output.borrow_and_update().alive {
+                if output.changed().await.is_err() {
+                    break;
+                }
+            }
+        };
+        let _ = tokio::time::timeout(DRAIN_TIMEOUT, drained).await;
+        let exit = ExitInfo {
+            time: now(),
+            code: status.as_ref().ok().and_then(|status| status.code()),
+            signal: status.as_ref().ok().and_then(exit_signal),
+            lines: output.borrow().tail(EXIT_OUTPUT_LINES),
+        };
         if save::access(name.as_str()).is_err() {
             println!(
                 "[!] An error occoured when attempting to set the access time of save \"{name}\""
             )
         }
+        let settings = match RestartSettings::read(name.as_str()) {
+            Ok(settings) => Some(settings),
+            Err(_) => {
+                println!("[!] An error occoured when attempting to read the restart policy of save \"{name}\"");
+                None
+            }
+        };
         let mut instances = INSTANCES.write().await;
         if let Some(instance) = instances.get_mut(&*name) {
-            instance.status = InstanceStatus::Offline;
+            // exiting while in Shutdown means mc-manager asked for it
+            let requested = instance.status == InstanceStatus::Shutdown;
+            instance.last_exit = Some(Arc::new(exit));
+            if failed && !requested {
+                instance.status = InstanceStatus::Crashed;
+                instance.crashes += 1;
+                println!("[{name}] Java process crashed");
+            } else {
+                instance.status = InstanceStatus::Offline;
+            }
+            if let Some(settings) = settings.filter(|_| !requested && !is_shutdown()) {
+                let restart = match settings.policy {
+                    RestartPolicy::Never => false,
+                    RestartPolicy::OnFailure => failed,
+                    RestartPolicy::Always => true,
+                };
+                if restart && instance.retries < settings.max_retries {
+                    let delay = settings.delay(instance.retries);
+                    instance.pending_restart = true;
+                    println!("[{name}] Restarting in {} seconds", delay.as_secs());
+                    tokio::spawn(restart_instance(name.clone(), instance.retries + 1, delay));
+                } else if restart {
+                    println!("[{name}] Not restarting, too many consecutive restarts");
+                }
+            }
             println!("[{name}] Waiter thread finished");
         } else {
             println!("[{name}] Waiter thread finished, and its instance was removed");
         }
     });
     let name: Arc<String> = name_arc.clone();
+    let stderr_vector = vector.clone();
+    // reads stderr, where the jvm reports errors like an unsupported class version or running out of memory
+    let stderr_reader = tokio::spawn(async move {
+        let mut line = Vec::with_capacity(4 * 1024);
+        let mut stderr = stderr;
+        loop {
+            line.clear();
+            line.extend_from_slice(STDERR_PREFIX);
+            match stderr.read_until(b'\n', &mut line).await {
+                Ok(0) => break,
+                Ok(_) => {
+                    // the last line may not end with a newline
+                    if !line.ends_with(b"\n") {
+                        line.push(b'\n');
+                    }
+                    stderr_vector.write(&line).await;
+                    println!("[{name}] {}", String::from_utf8_lossy(&line));
+                }
+                Err(error) => {
+                    println!("[{name}] Error reading process stderr: {:?}", error);
+                    break;
+                }
+            }
+        }
+    });
+    let name: Arc<String> = name_arc.clone();
     // reads and parses stdout
     tokio::spawn(async move {
         println!("[{name}] Reader task spawned");
@@ -192,10 +605,9 @@ pub async fn start_instance(name: &str) -> Result<(), ApiError> {
                         if let Some(instance) = instances.get_mut(name.as_str()) {
                             if instance.status == InstanceStatus::Loading {
                                 instance.status = InstanceStatus::Online;
-                                if is_shutdown() {
-                                    if instance.stop().await.is_err() {
-                                        panic!("could not send stop command through stdin");
-                                    }
+                                instance.retries = 0;
+                                if is_shutdown() && instance.stop().await.is_err() {
+                                    panic!("could not send stop command through stdin");
                                 }
                             }
                         } else {
@@ -211,6 +623,8 @@ pub async fn start_instance(name: &str) -> Result<(), ApiError> {
                 }
             }
         }
+        // the output is only over once both pipes are closed
+        let _ = stderr_reader.await;
         vector.finish().await;
         println!("[{name}] Reader task finished");
     });
@@ -229,6 +643,17 @@ pub async fn stop_instance(name: &str) -> Result<(), ApiError> {
     }
 }
 
+/// kills the instance, returns immedialty, will return an error if it is not running
+pub async fn kill_instance(name: &str) -> Result<(), ApiError> {
+    save::exists(name)?;
+    let mut instances = INSTANCES.write().await;
+    if let Some(instance) = instances.get_mut(name) {
+        instance.kill()
+    } else {
+        Err(ApiError::BadInstanceStatus(InstanceStatus::Cold))
+    }
+}
+
 /// checks if the instance is online, may returns an error if it is not online
 pub async fn query_instance(name: &str) -> Result<InstanceStatus, ApiError> {
     save::exists(name)?;
@@ -262,8 +687,8 @@ pub async fn write_instance(name: &str, command: &str) -> Result<(), ApiError> {
         return stop_instance(name).await;
     }
     let mut out = String::new();
-    if command.starts_with('/') {
-        out.push_str(&command[1..]);
+    if let Some(command) = command.strip_prefix('/') {
+        out.push_str(command);
     } else {
         out.push_str("say ");
         out.push_str(command);
@@ -289,21 +714,79 @@ pub async fn write_instance(name: &str, command: &str) -> Result<(), ApiError> {
     }
 }
 
-pub async fn instance_status_summary() -> String {
+/// disables autosave and flushes the world to disk, returns once the server confirms it saved
+///
+/// autosave must be enabled again with `resume_instance_saving`, even if this fails
+pub async fn flush_instance(name: &str) -> Result<(), ApiError> {
+    let vector = read_instance(name).await?;
+    let mut subscription = vector.subscribe();
+    let offset = subscription.borrow_and_update().end();
+    write_instance(name, "/save-off").await?;
+    write_instance(name, "/save-all flush").await?;
+    let saved = async {
+        loop {
+            {
+                let borrow = subscription.borrow_and_update();
+                let output = borrow.since(offset);
+                if output.split(|x| *x == b'\n').any(is_saved_line) {
+                    return Ok(());
+                }
+                if !borrow.alive {
+                    return Err(ApiError::BadInstanceStatus(InstanceStatus::Offline));
+                }
+            }
+            if subscription.changed().await.is_err() {
+                return Err(ApiError::BadInstanceStatus(InstanceStatus::Offline));
+            }
+        }
+    };
+    match tokio::time::timeout(FLUSH_TIMEOUT, saved).await {
+        Ok(result) => result,
+        Err(_) => Err(ApiError::IOError("the server did not confirm the save in time".to_owned())),
+    }
+}
+
+/// enables autosave again after `flush_instance`
+pub async fn resume_instance_saving(name: &str) -> Result<(), ApiError> {
+    write_instance(name, "/save-on").await
+}
+
+pub async fn instance_status_summary(visible: impl Fn(&str) -> bool) -> String {
     let mut out = String::with_capacity(4 * 1024);
     out.push('{');
     append_comma_separated(
-        INSTANCES.read().await.iter(),
+        INSTANCES
+            .read()
+            .await
+            .iter()
+            .filter(|(name, _)| visible(name)),
         &mut out,
         |out, (name, instance)| {
             append_json_string(out, name);
             match instance.status {
                 InstanceStatus::Cold => unreachable!(),
-                InstanceStatus::Loading => *out += r#":"loading""#,
-                InstanceStatus::Online => *out += r#":"online""#,
-                InstanceStatus::Shutdown => *out += r#":"shutdown""#,
-                InstanceStatus::Offline => *out += r#":"offline""#,
+                InstanceStatus::Loading => *out += r#":{"status":"loading""#,
+                InstanceStatus::Online => *out += r#":{"status":"online""#,
+                InstanceStatus::Shutdown => *out += r#":{"status":"shutdown""#,
+                InstanceStatus::Offline => *out += r#":{"status":"offline""#,
+                InstanceStatus::Crashed => *out += r#":{"status":"crashed""#,
+            }
+            *out += r#","crashes":"#;
+            *out += &instance.crashes.to_string();
+            if let Some(exit) = &instance.last_exit {
+                *out += r#","exit":{"time":"#;
+                append_json_string(out, &exit.time);
+                *out += r#","code":"#;
+                append_optional_number(out, exit.code);
+                *out += r#","signal":"#;
+                append_optional_number(out, exit.signal);
+                *out += r#","lines":["#;
+                append_comma_separated(exit.lines.iter(), out, |out, line| {
+                    append_json_string(out, line)
+                });
+                *out += "]}";
             }
+            *out += "}";
         },
     );
     out.push('}');
@@ -314,12 +797,99 @@ pub async fn instance_status_summary() -> String {
 pub async fn stop_all_instances() {
     println!("[*] Shutting down all instances");
     for (_, instance) in INSTANCES.write().await.iter_mut() {
-        if instance.status == InstanceStatus::Online {
-            if instance.stop().await.is_err() {
-                panic!("could not send stop command through stdin");
+        if instance.status == InstanceStatus::Online && instance.stop().await.is_err() {
+            panic!("could not send stop command through stdin");
+        }
+        if instance.status == InstanceStatus::Loading {
+            // it will be stopped when it finishes loading, but it may never finish
+            instance.schedule_kill();
+        }
+        instance.pending_restart = false;
+        instance.vector.sender.send_modify(|output| output.alive = false);
+    }
+}
+
+/// waits for all java processes to exit, killing the ones still running after the timeout
+///
+/// returns false if some process could not be confirmed to have exited
+pub async fn wait_all_instances(timeout: Duration) -> bool {
+    let start = Instant::now();
+    let mut killed = false;
+    loop {
+        {
+            let mut instances = INSTANCES.write().await;
+            if !instances.values().any(Instance::is_running) {
+                return true;
+            }
+            if !killed && start.elapsed() >= timeout {
+                println!("[!] Some instances did not stop in time, killing them");
+                for (_, instance) in instances.iter_mut() {
+                    if instance.is_running() {
+                        let _ = instance.kill();
+                    }
+                }
+                killed = true;
+            } else if start.elapsed() >= timeout + Duration::from_secs(10) {
+                return false;
             }
         }
-        let _ = instance.vector.sender.send((Vec::new(), false));
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+}
+
+fn append_optional_number(out: &mut String, number: Option<i32>) {
+    match number {
+        Some(number) => *out += &number.to_string(),
+        None => *out += "null",
+    }
+}
+
+#[cfg(unix)]
+fn exit_signal(status: &std::process::ExitStatus) -> Option<i32> {
+    std::os::unix::process::ExitStatusExt::signal(status)
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: &std::process::ExitStatus) -> Option<i32> {
+    None
+}
+
+/// true for the line the server logs when `save-all` finishes, like
+/// `[12:00:00] [Server thread/INFO]: Saved the game`, which players can not fake in the chat
+fn is_saved_line(line: &[u8]) -> bool {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    // very old versions, like `2013-10-25 12:00:00 [INFO] Saved the world`
+    if let Some(stamp) = line.strip_suffix(b" [INFO] Saved the world") {
+        let stamp = std::str::from_utf8(stamp).unwrap_or_default();
+        return chrono::NaiveDateTime::parse_from_str(stamp, TIME_FORMAT).is_ok();
+    }
+    // older versions say world instead of game
+    let Some(prefix) = line
+        .strip_suffix(b": Saved the game")
+        .or_else(|| line.strip_suffix(b": Saved the world"))
+    else {
+        return false;
+    };
+    if line.starts_with(STDERR_PREFIX) || !bytes_contains(prefix, b"INFO]") {
+        return false;
+    }
+    // only the tags of the time, thread and logger come before the message, chat has the player name
+    let mut rest = prefix;
+    loop {
+        let Some(tag) = rest.strip_prefix(b"[") else {
+            return false;
+        };
+        let Some(end) = tag.iter().position(|x| *x == b']') else {
+            return false;
+        };
+        rest = &tag[end + 1..];
+        if rest.is_empty() {
+            return true;
+        }
+        let Some(next) = rest.strip_prefix(b" ") else {
+            return false;
+        };
+        rest = next;
     }
 }
 
@@ -338,11 +908,48 @@ fn bytes_contains(haystack: &[u8], needle: &[u8]) -> bool {
     false
 }
 
-pub fn set_java_path(java: String) {
-    let mut lock = JAVA_PATH.lock().expect("JAVA_PATH lock is poisoned");
-    *lock = java;
+pub fn set_stop_timeout(seconds: u64) {
+    STOP_TIMEOUT.store(seconds, Ordering::Relaxed);
+}
+
+pub fn get_stop_timeout() -> Duration {
+    Duration::from_secs(STOP_TIMEOUT.load(Ordering::Relaxed))
 }
 
-pub fn get_java_path<'a>() -> std::sync::MutexGuard<'a, String> {
-    JAVA_PATH.lock().expect("JAVA_PATH lock is poisoned")
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_lines() {
+        for line in [
+            &b"[12:00:00] [Server thread/INFO]: Saved the game"[..],
+            b"[12:00:00] [Server thread/INFO]: Saved the game\r",
+            b"[12:00:00] [Server thread/INFO]: Saved the world",
+            b"[12:00:00 INFO]: Saved the game",
+            b"[12:00:00] [Server thread/INFO] [minecraft/MinecraftServer]: Saved the game",
+            b"2013-10-25 12:00:00 [INFO] Saved the world",
+        ] {
+            assert!(is_saved_line(line), "{}", String::from_utf8_lossy(line));
+        }
+    }
+
+    #[test]
+    fn lines_that_are_not_saved() {
+        for line in [
+            &b""[..],
+            b"Saved the game",
+            b"[12:00:00] [Server thread/INFO]: Saving the game (this may take a moment!)",
+            b"[12:00:00] [Server thread/WARN]: Saved the game",
+            b"[12:00:00] [Server thread/INFO]: <Steve> a: Saved the game",
+            b"[12:00:00] [Server thread/INFO]: [Steve] : Saved the game",
+            b"[12:00:00] [Async Chat Thread - #0/INFO]: [Not Secure] <Steve> x: Saved the game",
+            b"[stderr] [12:00:00] [Server thread/INFO]: Saved the game",
+            b"[12:00:00] [Server thread/INFO]: Saved the game!",
+            b"2013-10-25 12:00:00 [INFO] <Steve> [INFO] Saved the world",
+            b"[stderr] 2013-10-25 12:00:00 [INFO] Saved the world",
+        ] {
+            assert!(!is_saved_line(line), "{}", String::from_utf8_lossy(line));
+        }
+    }
 }
@@ -0,0 +1,307 @@
+use crate::utils::{append_comma_separated, append_json_string, ApiError};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// the java runtimes found when mc-manager started, the one configured in `java` first
+static RUNTIMES: std::sync::RwLock<Vec<Runtime>> = std::sync::RwLock::new(Vec::new());
+
+/// an installed java runtime that can run servers
+struct Runtime {
+    /// the java executable, as configured or found
+    path: String,
+    /// like 17.0.2 or 1.8.0_292
+    version: String,
+    /// like 17 or 8
+    major: u32,
+}
+
+/// the java versions a minecraft version can run on
+#[derive(Clone, Copy)]
+struct Requirement {
+    min: u32,
+    /// older versions and their mod loaders break on newer java
+    max: Option<u32>,
+}
+
+/// finds the java runtimes, configured in `java` and `java-runtimes` or installed in the usual places
+///
+/// runtimes whose version cannot be read are reported and skipped
+pub fn load(config: &HashMap<String, String>) {
+    let mut paths = Vec::new();
+    if let Some(java) = config.get("java").map(|x| x.trim()) {
+        if !java.is_empty() {
+            paths.push(PathBuf::from(java));
+        }
+    }
+    if let Some(runtimes) = config.get("java-runtimes") {
+        paths.extend(std::env::split_paths(runtimes.trim()).filter(|x| !x.as_os_str().is_empty()));
+    }
+    // optional, config files created by older versions do not have it
+    if config.get("java-discover").map(|x| x.trim()) != Some("false") {
+        paths.extend(discover());
+    }
+    let mut runtimes: Vec<Runtime> = Vec::with_capacity(paths.len());
+    let mut seen = Vec::with_capacity(paths.len());
+    for path in paths {
+        let canonical = std::fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if seen.contains(&canonical) {
+            continue;
+        }
+        seen.push(canonical);
+        let Some(path) = path.to_str() else {
+            continue;
+        };
+        match read_version(Path::new(path)) {
+            Some(version) => match parse_major(&version) {
+                Some(major) => {
+                    println!("[*] Found Java {version} at {path}");
+                    runtimes.push(Runtime {
+                        path: path.to_owned(),
+                        version,
+                        major,
+                    });
+                }
+                None => {
+                    println!("[!] Could not understand the version {version} of Java at {path}")
+                }
+            },
+            None => println!("[!] Could not read the version of Java at {path}"),
+        }
+    }
+    if runtimes.is_empty() {
+        println!("[!] No Java runtime was found, configure one with the property java");
+    }
+    *RUNTIMES.write().expect("RUNTIMES lock is poisoned") = runtimes;
+}
+
+/// returns a valid json with the runtimes found, in the order they are preferred
+pub fn list() -> String {
+    let runtimes = RUNTIMES.read().expect("RUNTIMES lock is poisoned");
+    let mut out = String::with_capacity(128 * runtimes.len() + 16);
+    out += r#"{"runtimes":["#;
+    append_comma_separated(runtimes.iter(), &mut out, |out, runtime| {
+        *out += r#"{"path":"#;
+        append_json_string(out, &runtime.path);
+        *out += r#","version":"#;
+        append_json_string(out, &runtime.version);
+        *out += r#","major":"#;
+        *out += &runtime.major.to_string();
+        *out += "}";
+    });
+    out += "]}";
+    out
+}
+
+/// picks the java executable a save runs with
+///
+/// `pinned` is the value of `mc-manager-java`, empty picks the oldest runtime the server version runs on,
+/// a number picks a runtime of that major version and anything else must be the path of a runtime found
+pub fn select(pinned: &str, server_version: &str) -> Result<String, ApiError> {
+    let runtimes = RUNTIMES.read().expect("RUNTIMES lock is poisoned");
+    if !pinned.is_empty() {
+        let runtime = match pinned.parse::<u32>() {
+            Ok(major) => runtimes.iter().find(|runtime| runtime.major == major),
+            Err(_) => runtimes.iter().find(|runtime| runtime.path == pinned),
+        };
+        return match runtime {
+            Some(runtime) => Ok(runtime.path.clone()),
+            None => Err(ApiError::JavaNotFound(pinned.to_owned())),
+        };
+    }
+    let Some(requirement) = requirement(server_version) else {
+        // an unknown version, like a modded one, runs with the runtime configured in java
+        return match runtimes.first() {
+            Some(runtime) => Ok(runtime.path.clone()),
+            None => Err(ApiError::JavaNotFound(String::new())),
+        };
+    };
+    runtimes
+        .iter()
+        .filter(|runtime| requirement.accepts(runtime.major))
+        .min_by_key(|runtime| runtime.major)
+        .map(|runtime| runtime.path.clone())
+        .ok_or_else(|| ApiError::JavaNotFound(requirement.to_string()))
+}
+
+impl Requirement {
+    fn accepts(self, major: u32) -> bool {
+        major >= self.min && self.max.is_none_or(|max| major <= max)
+    }
+}
+
+impl std::fmt::Display for Requirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.max {
+            Some(max) => write!(f, "{}-{}", self.min, max),
+            None => write!(f, "{}+", self.min),
+        }
+    }
+}
+
+/// the java versions needed by a release like 1.20.4 or 26.1 or a snapshot like 24w14a, none if the version is not known
+fn requirement(version: &str) -> Option<Requirement> {
+    let newer = |min| Some(Requirement { min, max: None });
+    if let Some((year, week)) = version.split_once('w') {
+        let year: u32 = year.parse().ok()?;
+        let week: u32 = week.get(..2)?.parse().ok()?;
+        return match (year, week) {
+            (26.., _) => newer(25),
+            (24, 14..) | (25, _) => newer(21),
+            (21, 44..) | (22.., _) => newer(17),
+            (21, 19..) => newer(16),
+            _ => Some(Requirement {
+                min: 8,
+                max: Some(16),
+            }),
+        };
+    }
+    let mut parts = version.split(['.', '-']);
+    // since 26.1 releases are named after the year, like 26.1 or 26.1-snapshot-1
+    match parts.next()?.parse::<u32>().ok()? {
+        1 => {}
+        26.. => return newer(25),
+        25 => return newer(21),
+        _ => return None,
+    }
+    let minor: u32 = parts.next()?.parse().ok()?;
+    let patch: u32 = parts.next().and_then(|x| x.parse().ok()).unwrap_or(0);
+    match (minor, patch) {
+        (21.., _) | (20, 5..) => newer(21),
+        (18..=20, _) => newer(17),
+        (17, _) => newer(16),
+        _ => Some(Requirement {
+            min: 8,
+            max: Some(16),
+        }),
+    }
+}
+
+/// the java executables in the places runtimes are usually installed, and the one in JAVA_HOME
+fn discover() -> Vec<PathBuf> {
+    let executable = if cfg!(windows) { "java.exe" } else { "java" };
+    let mut homes = Vec::new();
+    if let Some(home) = std::env::var_os("JAVA_HOME") {
+        homes.push(PathBuf::from(home));
+    }
+    #[cfg(unix)]
+    let parents = [
+        "/usr/lib/jvm",
+        "/usr/lib64/jvm",
+        "/usr/java",
+        "/opt/java",
+        "/opt",
+        "/Library/Java/JavaVirtualMachines",
+    ];
+    #[cfg(windows)]
+    let parents = [
+        r"C:\Program Files\Java",
+        r"C:\Program Files\Eclipse Adoptium",
+        r"C:\Program Files\Microsoft",
+        r"C:\Program Files\Zulu",
+        r"C:\Program Files\Amazon Corretto",
+    ];
+    for parent in parents {
+        let Ok(entries) = std::fs::read_dir(parent) else {
+            continue;
+        };
+        let mut found: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+        found.sort();
+        for home in found {
+            // the layout of macos
+            homes.push(home.join("Contents").join("Home"));
+            homes.push(home);
+        }
+    }
+    homes
+        .into_iter()
+        .map(|home| home.join("bin").join(executable))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// reads the version from the release file of the runtime, or asks the executable
+fn read_version(path: &Path) -> Option<String> {
+    let release = path.parent()?.parent()?.join("release");
+    if let Ok(release) = std::fs::read_to_string(release) {
+        for line in release.lines() {
+            if let Some(version) = line.strip_prefix("JAVA_VERSION=") {
+                return Some(version.trim().trim_matches('"').to_owned());
+            }
+        }
+    }
+    // like: openjdk version "17.0.2" 2022-01-18
+    let output = std::process::Command::new(path)
+        .arg("-version")
+        .stdin(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    let output = String::from_utf8_lossy(&output.stderr);
+    let line = output.lines().find(|line| line.contains(" version \""))?;
+    let version = line.split('"').nth(1)?;
+    Some(version.to_owned())
+}
+
+/// 1.8.0_292 is java 8, the versions after it start with the major version, like 17.0.2
+fn parse_major(version: &str) -> Option<u32> {
+    let mut parts = version.split(|x: char| !x.is_ascii_digit());
+    match parts.next()?.parse().ok()? {
+        1 => parts.next()?.parse().ok(),
+        major => Some(major),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(version: &str) -> Option<(u32, Option<u32>)> {
+        requirement(version).map(|x| (x.min, x.max))
+    }
+
+    #[test]
+    fn releases() {
+        assert_eq!(range("1.8.9"), Some((8, Some(16))));
+        assert_eq!(range("1.16.5"), Some((8, Some(16))));
+        assert_eq!(range("1.17"), Some((16, None)));
+        assert_eq!(range("1.17.1"), Some((16, None)));
+        assert_eq!(range("1.18"), Some((17, None)));
+        assert_eq!(range("1.20.4"), Some((17, None)));
+        assert_eq!(range("1.20.5"), Some((21, None)));
+        assert_eq!(range("1.20.5-pre1"), Some((21, None)));
+        assert_eq!(range("1.21.4"), Some((21, None)));
+    }
+
+    #[test]
+    fn releases_named_after_the_year() {
+        assert_eq!(range("25.1"), Some((21, None)));
+        assert_eq!(range("26.1"), Some((25, None)));
+        assert_eq!(range("26.1-snapshot-1"), Some((25, None)));
+        assert_eq!(range("27.2"), Some((25, None)));
+    }
+
+    #[test]
+    fn snapshots() {
+        assert_eq!(range("21w18a"), Some((8, Some(16))));
+        assert_eq!(range("21w19a"), Some((16, None)));
+        assert_eq!(range("21w44a"), Some((17, None)));
+        assert_eq!(range("24w13a"), Some((17, None)));
+        assert_eq!(range("24w14a"), Some((21, None)));
+        assert_eq!(range("25w45a"), Some((21, None)));
+        assert_eq!(range("26w01a"), Some((25, None)));
+    }
+
+    #[test]
+    fn unknown_versions() {
+        for version in ["", "b1.7.3", "rd-132211", "2.0", "1", "xw14a", "24w1"] {
+            assert!(requirement(version).is_none(), "{version}");
+        }
+    }
+
+    #[test]
+    fn major_versions() {
+        assert_eq!(parse_major("1.8.0_292"), Some(8));
+        assert_eq!(parse_major("17.0.2"), Some(17));
+        assert_eq!(parse_major("21"), Some(21));
+        assert_eq!(parse_major(""), None);
+    }
+}
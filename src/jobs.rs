@@ -0,0 +1,247 @@
+use crate::instances::{flush_instance, query_instance, resume_instance_saving, InstanceStatus};
+use crate::loaders::{self, Flavor};
+use crate::state::{backup, save, SaveLock};
+use crate::versions;
+use crate::utils::{append_comma_separated, append_json_string, now, ApiError};
+use lazy_static::lazy_static;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+lazy_static! {
+    static ref JOBS: std::sync::Mutex<VecDeque<Job>> = std::sync::Mutex::new(VecDeque::new());
+}
+
+static NEXT_JOB_ID: AtomicU64 = AtomicU64::new(1);
+
+/// how many finished jobs are remembered
+const MAX_FINISHED_JOBS: usize = 64;
+
+/// a long operation running in the background, which the client can poll
+struct Job {
+    id: u64,
+    kind: &'static str,
+    save: String,
+    start_time: String,
+    /// a short name for what the job is doing now
+    stage: &'static str,
+    /// from 0 to 1, the progress of the current stage
+    progress: f64,
+    state: JobState,
+}
+
+enum JobState {
+    Running,
+    /// holds the result of the job, like the id of a backup
+    Done(Option<String>),
+    Failed(ApiError),
+}
+
+/// registers a new job, returns its id
+pub fn create(kind: &'static str, save: &str) -> u64 {
+    let id = NEXT_JOB_ID.fetch_add(1, Ordering::Relaxed);
+    let mut jobs = JOBS.lock().expect("JOBS lock is poisoned");
+    while jobs.len() > MAX_FINISHED_JOBS {
+        match jobs.iter().position(|job| !matches!(job.state, JobState::Running)) {
+            Some(index) => jobs.remove(index),
+            None => break,
+        };
+    }
+    jobs.push_back(Job {
+        id,
+        kind,
+        save: save.to_owned(),
+        start_time: now(),
+        stage: "queued",
+        progress: 0.0,
+        state: JobState::Running,
+    });
+    id
+}
+
+/// updates the stage and progress of a running job
+pub fn update(id: u64, stage: &'static str, progress: f64) {
+    let mut jobs = JOBS.lock().expect("JOBS lock is poisoned");
+    if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+        job.stage = stage;
+        job.progress = progress.clamp(0.0, 1.0);
+    }
+}
+
+/// marks the job as finished, with its result
+pub fn finish(id: u64, result: Result<Option<String>, ApiError>) {
+    let mut jobs = JOBS.lock().expect("JOBS lock is poisoned");
+    if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+        job.stage = "finished";
+        job.progress = 1.0;
+        job.state = match result {
+            Ok(value) => JobState::Done(value),
+            Err(error) => JobState::Failed(error),
+        };
+    }
+}
+
+/// returns a valid json with all jobs of visible saves that are running or finished recently
+pub fn summary(visible: impl Fn(&str) -> bool) -> String {
+    let jobs = JOBS.lock().expect("JOBS lock is poisoned");
+    let mut out = String::with_capacity(4 * 1024);
+    out += r#"{"jobs":["#;
+    append_comma_separated(
+        jobs.iter().filter(|job| visible(&job.save)),
+        &mut out,
+        append_job,
+    );
+    out += "]}";
+    out
+}
+
+/// returns a valid json describing the job, jobs of saves that are not visible are not found
+pub fn query(id: u64, visible: impl Fn(&str) -> bool) -> Result<String, ApiError> {
+    let jobs = JOBS.lock().expect("JOBS lock is poisoned");
+    let Some(job) = jobs.iter().find(|job| job.id == id && visible(&job.save)) else {
+        return Err(ApiError::JobNotFound);
+    };
+    let mut out = String::with_capacity(256);
+    append_job(&mut out, job);
+    Ok(out)
+}
+
+/// starts a backup of the save in the background, returns the id of the job
+///
+/// if the server is online, its autosave is paused while the backup is made
+pub fn start_backup(name: &str) -> u64 {
+    let id = create("backup", name);
+    let name = name.to_owned();
+    tokio::spawn(async move {
+        let result = run_backup(&name, false, id).await;
+        match &result {
+            Ok(backup) => println!("[{name}] Backup {backup} created"),
+            Err(_) => println!("[!] Backup of save \"{name}\" failed"),
+        }
+        finish(id, result.map(Some));
+    });
+    id
+}
+
+/// makes a backup of the save, reporting its progress in the job specified, returns the backup id
+pub async fn run_backup(name: &str, automatic: bool, id: u64) -> Result<String, ApiError> {
+    // taken before the status is checked, so the server can not be started in the meantime
+    let _lock = save::lock(name)?;
+    let online = match query_instance(name).await? {
+        InstanceStatus::Online => true,
+        InstanceStatus::Offline | InstanceStatus::Crashed | InstanceStatus::Cold => false,
+        status => return Err(status.to_error()),
+    };
+    if online {
+        update(id, "flush", 0.0);
+        if let Err(error) = flush_instance(name).await {
+            let _ = resume_instance_saving(name).await;
+            return Err(error);
+        }
+    }
+    let result = copy_backup(name, automatic, id).await;
+    if online {
+        update(id, "save-on", 1.0);
+        // the server may have been stopped in the meantime, in which case there is nothing to resume
+        let _ = resume_instance_saving(name).await;
+    }
+    result
+}
+
+/// archives the save, which must be locked by the caller and not written to by the server
+async fn copy_backup(name: &str, automatic: bool, id: u64) -> Result<String, ApiError> {
+    update(id, "copy", 0.0);
+    let owned_name = name.to_owned();
+    tokio::task::spawn_blocking(move || {
+        backup::create(&owned_name, automatic, |done, total| {
+            update(id, "copy", done as f64 / total.max(1) as f64)
+        })
+    })
+    .await
+    .unwrap_or_else(|error| Err(ApiError::IOError(error.to_string())))
+}
+
+/// changes the version of the save in the background, returns the id of the job
+///
+/// the version is downloaded and a backup is made before the jar is replaced,
+/// the result of the job is the id of the backup, to go back if the world does not load
+pub fn start_upgrade(name: &str, version: &str) -> u64 {
+    let id = create("upgrade", name);
+    let name = name.to_owned();
+    let version = version.to_owned();
+    tokio::spawn(async move {
+        let result = run_upgrade(&name, &version, id).await;
+        match &result {
+            Ok(_) => println!("[{name}] Changed to version {version}"),
+            Err(_) => println!("[!] Changing the version of save \"{name}\" failed"),
+        }
+        finish(id, result.map(Some));
+    });
+    id
+}
+
+async fn run_upgrade(name: &str, version: &str, id: u64) -> Result<String, ApiError> {
+    update(id, "download", 0.0);
+    versions::download(version).await?;
+    // held until the jar is replaced, starting a locked save fails in `spawn_instance` with SaveBusy
+    let _lock = save::lock(name)?;
+    match query_instance(name).await? {
+        InstanceStatus::Offline | InstanceStatus::Crashed | InstanceStatus::Cold => {}
+        status => return Err(status.to_error()),
+    }
+    let backup = copy_backup(name, false, id).await?;
+    update(id, "install", 1.0);
+    let (owned_name, owned_version) = (name.to_owned(), version.to_owned());
+    tokio::task::spawn_blocking(move || save::set_version(&owned_name, &owned_version))
+        .await
+        .unwrap_or_else(|error| Err(ApiError::IOError(error.to_string())))?;
+    Ok(backup)
+}
+
+/// installs the server of a flavor into a save that was just created, returns the id of the job
+///
+/// the save stays busy until the installation finishes, so it can not be started before
+pub fn start_install(lock: SaveLock, name: &str, flavor: Flavor, version: &str, build: &str) -> u64 {
+    let id = create("install", name);
+    let name = name.to_owned();
+    let version = version.to_owned();
+    let build = build.to_owned();
+    tokio::spawn(async move {
+        update(id, "install", 0.0);
+        let result = loaders::install(&name, flavor, &version, &build).await;
+        drop(lock);
+        match &result {
+            Ok(()) => println!("[{name}] Installed {} {build}", flavor.name()),
+            Err(_) => println!("[!] Installing {} on save \"{name}\" failed", flavor.name()),
+        }
+        finish(id, result.map(|_| None));
+    });
+    id
+}
+
+fn append_job(out: &mut String, job: &Job) {
+    *out += r#"{"id":"#;
+    *out += &job.id.to_string();
+    *out += r#","kind":"#;
+    append_json_string(out, job.kind);
+    *out += r#","save":"#;
+    append_json_string(out, &job.save);
+    *out += r#","start_time":"#;
+    append_json_string(out, &job.start_time);
+    *out += r#","stage":"#;
+    append_json_string(out, job.stage);
+    *out += r#","progress":"#;
+    *out += &format!("{:.3}", job.progress);
+    match &job.state {
+        JobState::Running => *out += r#","state":"running""#,
+        JobState::Done(Some(result)) => {
+            *out += r#","state":"done","result":"#;
+            append_json_string(out, result);
+        }
+        JobState::Done(None) => *out += r#","state":"done""#,
+        JobState::Failed(error) => {
+            *out += r#","state":"failed","error":"#;
+            *out += &error.to_json();
+        }
+    }
+    *out += "}";
+}
@@ -0,0 +1,378 @@
+use crate::java;
+use crate::properties::{valid_string, write_properties, PropValue};
+use crate::utils::ApiError;
+use crate::versions::{download_file, fetch_json, Expected};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+const PAPER_API: &str = "https://api.papermc.io/v2/projects/paper";
+const PURPUR_API: &str = "https://api.purpurmc.org/v2/purpur";
+const FABRIC_API: &str = "https://meta.fabricmc.net/v2/versions";
+const FORGE_PROMOTIONS: &str =
+    "https://files.minecraftforge.net/net/minecraftforge/forge/promotions_slim.json";
+const FORGE_MAVEN: &str = "https://maven.minecraftforge.net/net/minecraftforge/forge";
+const FORGE_BUILDS: &str =
+    "https://files.minecraftforge.net/net/minecraftforge/forge/maven-metadata.json";
+const NEOFORGE_VERSIONS: &str =
+    "https://maven.neoforged.net/api/maven/versions/releases/net/neoforged/neoforge";
+const NEOFORGE_MAVEN: &str = "https://maven.neoforged.net/releases/net/neoforged/neoforge";
+
+/// the installers are downloaded with this name into the save, and removed once they finish
+const INSTALLER_FILE: &str = "installer.jar";
+
+/// how many of the last lines written by a failed installer are reported
+const INSTALLER_ERROR_LINES: usize = 20;
+
+/// the server software of a save, recorded in `mc-manager-server-flavor`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Flavor {
+    Vanilla,
+    Paper,
+    Purpur,
+    Fabric,
+    Forge,
+    NeoForge,
+}
+
+#[derive(Deserialize)]
+struct PaperBuilds {
+    builds: Vec<PaperBuild>,
+}
+
+#[derive(Deserialize)]
+struct PaperBuild {
+    build: u64,
+    /// default or experimental
+    channel: String,
+    downloads: PaperDownloads,
+}
+
+#[derive(Deserialize)]
+struct PaperDownloads {
+    application: PaperDownload,
+}
+
+#[derive(Deserialize)]
+struct PaperDownload {
+    name: String,
+    sha256: String,
+}
+
+#[derive(Deserialize)]
+struct PurpurBuilds {
+    builds: PurpurLatest,
+}
+
+#[derive(Deserialize)]
+struct PurpurLatest {
+    latest: String,
+    all: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct FabricLoader {
+    loader: FabricVersion,
+}
+
+#[derive(Deserialize)]
+struct FabricVersion {
+    version: String,
+    stable: bool,
+}
+
+#[derive(Deserialize)]
+struct ForgePromotions {
+    /// like 1.20.4-recommended or 1.20.4-latest to the build
+    promos: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct NeoForgeVersions {
+    /// oldest first
+    versions: Vec<String>,
+}
+
+impl Flavor {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "vanilla" => Some(Flavor::Vanilla),
+            "paper" => Some(Flavor::Paper),
+            "purpur" => Some(Flavor::Purpur),
+            "fabric" => Some(Flavor::Fabric),
+            "forge" => Some(Flavor::Forge),
+            "neoforge" => Some(Flavor::NeoForge),
+            _ => None,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Flavor::Vanilla => "vanilla",
+            Flavor::Paper => "paper",
+            Flavor::Purpur => "purpur",
+            Flavor::Fabric => "fabric",
+            Flavor::Forge => "forge",
+            Flavor::NeoForge => "neoforge",
+        }
+    }
+}
+
+/// checks that `build` exists for the minecraft version, or finds the newest stable one if it is none
+pub async fn resolve(
+    flavor: Flavor,
+    version: &str,
+    build: Option<&str>,
+) -> Result<String, ApiError> {
+    if build.is_some_and(|build| !is_valid_build(build)) {
+        return Err(ApiError::LoaderNotFound);
+    }
+    let resolved = match flavor {
+        Flavor::Vanilla => Some(String::new()),
+        Flavor::Paper => {
+            let builds: PaperBuilds =
+                fetch_or_not_found(&format!("{PAPER_API}/versions/{version}/builds")).await?;
+            let found = match build {
+                Some(build) => builds.builds.iter().rfind(|x| x.build.to_string() == build),
+                None => builds.builds.iter().rfind(|x| x.channel == "default"),
+            };
+            found.map(|x| x.build.to_string())
+        }
+        Flavor::Purpur => {
+            let builds: PurpurBuilds =
+                fetch_or_not_found(&format!("{PURPUR_API}/{version}")).await?;
+            match build {
+                Some(build) => builds.builds.all.into_iter().find(|x| x == build),
+                None => Some(builds.builds.latest),
+            }
+        }
+        Flavor::Fabric => {
+            let loaders: Vec<FabricLoader> =
+                fetch_or_not_found(&format!("{FABRIC_API}/loader/{version}")).await?;
+            match build {
+                Some(build) => loaders.iter().find(|x| x.loader.version == build),
+                None => loaders.iter().find(|x| x.loader.stable),
+            }
+            .map(|x| x.loader.version.clone())
+        }
+        Flavor::Forge => match build {
+            Some(build) => {
+                // like 1.20.4 to 1.20.4-49.0.30
+                let builds: HashMap<String, Vec<String>> = fetch_json(FORGE_BUILDS).await?;
+                let full = format!("{version}-{build}");
+                builds
+                    .get(version)
+                    .is_some_and(|x| x.contains(&full))
+                    .then(|| build.to_owned())
+            }
+            None => {
+                let promotions: ForgePromotions = fetch_json(FORGE_PROMOTIONS).await?;
+                let promos = &promotions.promos;
+                promos
+                    .get(&format!("{version}-recommended"))
+                    .or_else(|| promos.get(&format!("{version}-latest")))
+                    .cloned()
+            }
+        },
+        Flavor::NeoForge => {
+            // 1.20.4 is built by the 20.4.x releases, and 1.21 by 21.0.x
+            let mut parts = version.split('.').skip(1);
+            let prefix = match (parts.next(), parts.next()) {
+                (Some(minor), patch) => format!("{minor}.{}.", patch.unwrap_or("0")),
+                (None, _) => return Err(ApiError::LoaderNotFound),
+            };
+            let versions: NeoForgeVersions = fetch_json(NEOFORGE_VERSIONS).await?;
+            let mut matching = versions.versions.iter().filter(|x| x.starts_with(&prefix));
+            match build {
+                Some(build) => matching.find(|x| *x == build).cloned(),
+                None => {
+                    let matching: Vec<&String> = matching.collect();
+                    matching
+                        .iter()
+                        .rfind(|x| !x.contains("beta"))
+                        .or(matching.last())
+                        .map(|x| x.to_string())
+                }
+            }
+        }
+    };
+    resolved.ok_or(ApiError::LoaderNotFound)
+}
+
+/// installs a build of the flavor into the save, which must already have the vanilla server of the version
+///
+/// forge and neoforge run their installer, which needs java and downloads the libraries of the server
+pub async fn install(
+    save: &str,
+    flavor: Flavor,
+    version: &str,
+    build: &str,
+) -> Result<(), ApiError> {
+    let dir = format!("saves/{save}");
+    let jar = match flavor {
+        Flavor::Vanilla => "server.jar".to_owned(),
+        Flavor::Paper => {
+            let builds: PaperBuilds =
+                fetch_json(&format!("{PAPER_API}/versions/{version}/builds")).await?;
+            let Some(found) = builds.builds.iter().find(|x| x.build.to_string() == build) else {
+                return Err(ApiError::LoaderNotFound);
+            };
+            let download = &found.downloads.application;
+            if !valid_string("mc-manager-server-jar", &download.name) {
+                return Err(ApiError::DownloadFailed(format!(
+                    "bad file name {}",
+                    download.name
+                )));
+            }
+            let url = format!(
+                "{PAPER_API}/versions/{version}/builds/{build}/downloads/{}",
+                download.name
+            );
+            let expected = Expected {
+                sha256: Some(&download.sha256),
+                ..Default::default()
+            };
+            download_file(&url, &format!("{dir}/{}", download.name), expected).await?;
+            download.name.clone()
+        }
+        Flavor::Purpur => {
+            let jar = format!("purpur-{version}-{build}.jar");
+            // purpur only publishes an md5
+            let url = format!("{PURPUR_API}/{version}/{build}/download");
+            let expected = Expected {
+                jar: true,
+                ..Default::default()
+            };
+            download_file(&url, &format!("{dir}/{jar}"), expected).await?;
+            jar
+        }
+        Flavor::Fabric => {
+            let installers: Vec<FabricVersion> =
+                fetch_json(&format!("{FABRIC_API}/installer")).await?;
+            let Some(installer) = installers.iter().find(|x| x.stable) else {
+                return Err(ApiError::LoaderNotFound);
+            };
+            // a launcher that downloads the loader and its libraries when the server first starts
+            let jar = format!("fabric-server-{version}-{build}.jar");
+            let url = format!(
+                "{FABRIC_API}/loader/{version}/{build}/{}/server/jar",
+                installer.version
+            );
+            let expected = Expected {
+                jar: true,
+                ..Default::default()
+            };
+            download_file(&url, &format!("{dir}/{jar}"), expected).await?;
+            jar
+        }
+        Flavor::Forge | Flavor::NeoForge => {
+            let url = match flavor {
+                Flavor::Forge => {
+                    format!("{FORGE_MAVEN}/{version}-{build}/forge-{version}-{build}-installer.jar")
+                }
+                _ => format!("{NEOFORGE_MAVEN}/{build}/neoforge-{build}-installer.jar"),
+            };
+            download_file(
+                &url,
+                &format!("{dir}/{INSTALLER_FILE}"),
+                Expected {
+                    jar: true,
+                    ..Default::default()
+                },
+            )
+            .await?;
+            let result = run_installer(&dir, version).await;
+            let _ = std::fs::remove_file(format!("{dir}/{INSTALLER_FILE}"));
+            let _ = std::fs::remove_file(format!("{dir}/{INSTALLER_FILE}.log"));
+            result?;
+            if args_file(&dir, flavor, version, build).is_some() {
+                // launched with the arguments file, the jar is not used
+                "server.jar".to_owned()
+            } else {
+                // versions before 1.17 install a jar that runs the server
+                let candidates = [
+                    format!("forge-{version}-{build}.jar"),
+                    format!("forge-{version}-{build}-universal.jar"),
+                ];
+                match candidates
+                    .into_iter()
+                    .find(|x| Path::new(&dir).join(x).is_file())
+                {
+                    Some(jar) => jar,
+                    None => {
+                        return Err(ApiError::JavaError(
+                            "the installer did not create a server".to_owned(),
+                        ))
+                    }
+                }
+            }
+        }
+    };
+    let mut values = HashMap::new();
+    values.insert(
+        "mc-manager-server-flavor".to_owned(),
+        PropValue::String(flavor.name().to_owned()),
+    );
+    values.insert(
+        "mc-manager-server-build".to_owned(),
+        PropValue::String(build.to_owned()),
+    );
+    values.insert("mc-manager-server-jar".to_owned(), PropValue::String(jar));
+    write_properties(format!("{dir}/server.properties"), values)
+}
+
+/// the file with the arguments that launch forge and neoforge since 1.17, relative to the save folder
+///
+/// none if the flavor does not use one, or if the installer did not create it
+pub fn args_file(dir: &str, flavor: Flavor, version: &str, build: &str) -> Option<String> {
+    let name = if cfg!(windows) {
+        "win_args.txt"
+    } else {
+        "unix_args.txt"
+    };
+    let file = match flavor {
+        Flavor::Forge => format!("libraries/net/minecraftforge/forge/{version}-{build}/{name}"),
+        Flavor::NeoForge => format!("libraries/net/neoforged/neoforge/{build}/{name}"),
+        _ => return None,
+    };
+    if !is_valid_build(build) {
+        return None;
+    }
+    Path::new(dir).join(&file).is_file().then_some(file)
+}
+
+async fn run_installer(dir: &str, version: &str) -> Result<(), ApiError> {
+    let java = java::select("", version)?;
+    let output = tokio::process::Command::new(java)
+        .args(["-jar", INSTALLER_FILE, "--installServer"])
+        .current_dir(dir)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .await
+        .map_err(|error| ApiError::JavaError(error.to_string()))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = text.lines().collect();
+    let tail = &lines[lines.len().saturating_sub(INSTALLER_ERROR_LINES)..];
+    Err(ApiError::JavaError(tail.join("\n")))
+}
+
+/// a 404 means the minecraft version is not supported by the flavor
+async fn fetch_or_not_found<T: serde::de::DeserializeOwned>(url: &str) -> Result<T, ApiError> {
+    match fetch_json(url).await {
+        Err(ApiError::DownloadFailed(error)) if error.contains("404") => {
+            Err(ApiError::LoaderNotFound)
+        }
+        result => result,
+    }
+}
+
+/// builds end up in urls and file names
+fn is_valid_build(build: &str) -> bool {
+    !build.is_empty()
+        && !build.starts_with('.')
+        && build
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || matches!(x, '.' | '-' | '+' | '_'))
+}
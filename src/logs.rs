@@ -0,0 +1,577 @@
+use crate::properties::read_properties;
+use crate::state::save;
+use crate::utils::{
+    append_comma_separated, append_json_string, format_system_time, time_in_range, ApiError,
+    TIME_FORMAT,
+};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// the folder inside the save where the console output is kept
+pub const LOGS_DIR: &str = "mc-manager-logs";
+
+/// the name of a log file is the time it was created
+const FILE_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+
+/// the most bytes returned by a single `LogWriter::read`
+const READ_CHUNK: usize = 64 * 1024;
+
+/// the most bytes returned by a single `read`
+const MAX_READ_LENGTH: u64 = 1024 * 1024;
+
+/// the most matches returned by a single `search`
+const MAX_SEARCH_MATCHES: usize = 1000;
+
+/// matched lines longer than this are cut
+const MAX_MATCH_LENGTH: usize = 1000;
+
+/// who wrote a log file
+#[derive(Clone, Copy)]
+pub enum Source {
+    /// the console output kept by mc-manager, in `LOGS_DIR`
+    Manager,
+    /// the logs minecraft itself keeps in the logs folder, older ones are gzipped
+    Server,
+}
+
+/// the range of a log file returned by `read`
+#[derive(Deserialize)]
+pub struct Range {
+    #[serde(default)]
+    offset: u64,
+    length: Option<u64>,
+}
+
+/// the parameters of `search`, the times are in the format of `utils::now` or a prefix of it
+#[derive(Deserialize)]
+pub struct Search {
+    query: String,
+    /// if false the query is searched as plain text
+    #[serde(default)]
+    regex: bool,
+    #[serde(default)]
+    ignore_case: bool,
+    from: Option<String>,
+    to: Option<String>,
+    /// how many of the newest matches to return
+    limit: Option<usize>,
+}
+
+/// a line that matched a search
+struct Match {
+    source: Source,
+    file: String,
+    /// where the line starts, can be passed to `read`
+    offset: usize,
+    time: Option<NaiveDateTime>,
+    text: String,
+}
+
+/// how a line of a log tells when it was written
+enum Stamp {
+    /// like `2013-10-25 12:00:00 [INFO]`, used by very old versions
+    Full(NaiveDateTime),
+    /// like `[12:00:00] [Server thread/INFO]`, the date comes from the name of the file
+    TimeOfDay(NaiveTime),
+}
+
+/// the log settings of a save, read from its `mc-manager-log-*` properties
+struct LogSettings {
+    /// a new file is started once the current one would grow past this many bytes
+    max_size: u64,
+    /// files last written longer ago than this are deleted
+    retention: Duration,
+}
+
+/// the files written by one run of an instance
+///
+/// a new file is started when the current one gets too big or a new day starts,
+/// the offsets are the same used by the console, counted from the start of the run
+pub struct LogWriter {
+    dir: PathBuf,
+    settings: LogSettings,
+    /// None if the logs could not be written, in which case nothing else is written for this run
+    file: Option<File>,
+    segments: Vec<Segment>,
+    /// the day the current file was created
+    day: chrono::NaiveDate,
+}
+
+/// one file of a run
+struct Segment {
+    path: PathBuf,
+    /// the offset of the first byte of the file
+    start: usize,
+    /// how many bytes were written to the file
+    len: usize,
+}
+
+impl LogSettings {
+    fn read(name: &str) -> Result<Self, ApiError> {
+        let properties = read_properties(format!("saves/{name}/server.properties"))?;
+        let max_size: u64 = match properties.get("mc-manager-log-max-size") {
+            Some(value) => match value.trim().parse() {
+                Ok(value) => value,
+                Err(_) => return Err(ApiError::BadConfig("mc-manager-log-max-size".to_owned())),
+            },
+            None => 10,
+        };
+        let retention: u64 = match properties.get("mc-manager-log-retention") {
+            Some(value) => match value.trim().parse() {
+                Ok(value) => value,
+                Err(_) => return Err(ApiError::BadConfig("mc-manager-log-retention".to_owned())),
+            },
+            None => 14,
+        };
+        Ok(LogSettings {
+            max_size: max_size.max(1) * 1024 * 1024,
+            retention: Duration::from_secs(retention.max(1) * 24 * 60 * 60),
+        })
+    }
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        LogSettings {
+            max_size: 10 * 1024 * 1024,
+            retention: Duration::from_secs(14 * 24 * 60 * 60),
+        }
+    }
+}
+
+impl LogWriter {
+    /// starts the logs of a new run of the save, deleting the files that are too old
+    ///
+    /// never fails, if the logs can not be written the console still works, only without history
+    pub fn open(name: &str) -> Self {
+        let settings = LogSettings::read(name).unwrap_or_else(|_| {
+            println!(
+                "[!] An error occoured when attempting to read the log settings of save \"{name}\""
+            );
+            LogSettings::default()
+        });
+        let mut writer = LogWriter {
+            dir: Path::new("saves").join(name).join(LOGS_DIR),
+            settings,
+            file: None,
+            segments: Vec::new(),
+            day: chrono::Local::now().date_naive(),
+        };
+        if let Err(error) = std::fs::create_dir_all(&writer.dir) {
+            println!("[{name}] Could not create the logs folder, {error}");
+            return writer;
+        }
+        writer.prune();
+        writer.next_file(0);
+        writer
+    }
+    /// appends the output that starts at `offset` to the logs
+    pub fn write(&mut self, offset: usize, data: &[u8]) {
+        let Some(last) = self.segments.last() else {
+            return;
+        };
+        let today = chrono::Local::now().date_naive();
+        let too_big = (last.len + data.len()) as u64 > self.settings.max_size;
+        if last.len > 0 && (too_big || today != self.day) {
+            self.prune();
+            self.next_file(offset);
+        }
+        let (Some(file), Some(last)) = (&mut self.file, self.segments.last_mut()) else {
+            return;
+        };
+        if let Err(error) = file.write_all(data) {
+            println!(
+                "[!] Could not write to \"{}\", {error}",
+                last.path.display()
+            );
+            self.file = None;
+            return;
+        }
+        last.len += data.len();
+    }
+    /// reads the output of this run from `from` up to `to`, from the first file that still has it
+    ///
+    /// returns the offset the data actually starts at, which is after `from` if part of the output
+    /// was never written or was deleted, and at most `READ_CHUNK` bytes
+    pub fn read(&self, from: usize, to: usize) -> Option<(usize, Vec<u8>)> {
+        for segment in &self.segments {
+            let start = from.max(segment.start);
+            let end = to.min(segment.start + segment.len).min(start + READ_CHUNK);
+            if start >= end {
+                continue;
+            }
+            let mut data = vec![0; end - start];
+            let result = File::open(&segment.path).and_then(|mut file| {
+                file.seek(SeekFrom::Start((start - segment.start) as u64))?;
+                file.read_exact(&mut data)
+            });
+            match result {
+                Ok(()) => return Some((start, data)),
+                Err(_) => continue,
+            }
+        }
+        None
+    }
+    fn next_file(&mut self, offset: usize) {
+        let stem = chrono::Local::now().format(FILE_FORMAT).to_string();
+        let mut path = self.dir.join(format!("{stem}.log"));
+        let mut index = 2;
+        // two files in the same second
+        while path.exists() {
+            path = self.dir.join(format!("{stem}_{index}.log"));
+            index += 1;
+        }
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => {
+                self.file = Some(file);
+                self.day = chrono::Local::now().date_naive();
+                self.segments.push(Segment {
+                    path,
+                    start: offset,
+                    len: 0,
+                });
+            }
+            Err(error) => {
+                println!("[!] Could not create \"{}\", {error}", path.display());
+                self.file = None;
+            }
+        }
+    }
+    /// deletes the log files last written longer ago than the retention
+    fn prune(&self) {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        let now = SystemTime::now();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension() != Some("log".as_ref()) {
+                continue;
+            }
+            let Ok(modified) = entry.metadata().and_then(|x| x.modified()) else {
+                continue;
+            };
+            if now.duration_since(modified).unwrap_or_default() > self.settings.retention {
+                match std::fs::remove_file(&path) {
+                    Ok(()) => {}
+                    Err(error) if error.kind() == ErrorKind::NotFound => {}
+                    Err(error) => println!("[!] Could not delete \"{}\", {error}", path.display()),
+                }
+            }
+        }
+    }
+}
+
+impl Source {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "manager" => Some(Source::Manager),
+            "server" => Some(Source::Server),
+            _ => None,
+        }
+    }
+    fn name(self) -> &'static str {
+        match self {
+            Source::Manager => "manager",
+            Source::Server => "server",
+        }
+    }
+    fn dir(self, save: &str) -> PathBuf {
+        match self {
+            Source::Manager => Path::new("saves").join(save).join(LOGS_DIR),
+            Source::Server => Path::new("saves").join(save).join("logs"),
+        }
+    }
+}
+
+/// returns a valid json with the log files of both sources, the most recently written first
+pub fn list(save: &str) -> Result<String, ApiError> {
+    save::exists(save)?;
+    let mut files = Vec::new();
+    for source in [Source::Manager, Source::Server] {
+        for (name, metadata) in log_files(save, source)? {
+            let modified = metadata.modified()?;
+            files.push((source, name, metadata.len(), modified));
+        }
+    }
+    files.sort_by_key(|file| std::cmp::Reverse(file.3));
+    let mut out = String::with_capacity(128 * files.len() + 16);
+    out += r#"{"logs":["#;
+    append_comma_separated(
+        files.iter(),
+        &mut out,
+        |out, (source, name, size, modified)| {
+            *out += r#"{"source":"#;
+            append_json_string(out, source.name());
+            *out += r#","name":"#;
+            append_json_string(out, name);
+            *out += r#","size":"#;
+            *out += &size.to_string();
+            *out += r#","modified":"#;
+            append_json_string(out, &format_system_time(*modified));
+            *out += r#","compressed":"#;
+            *out += if name.ends_with(".gz") {
+                "true"
+            } else {
+                "false"
+            };
+            *out += "}";
+        },
+    );
+    out += "]}";
+    Ok(out)
+}
+
+/// returns a valid json with part of a log file, gzipped files are read decompressed
+pub fn read(save: &str, source: Source, file: &str, range: &Range) -> Result<String, ApiError> {
+    let length = range
+        .length
+        .unwrap_or(READ_CHUNK as u64)
+        .min(MAX_READ_LENGTH);
+    let mut reader = open_log(save, source, file)?;
+    std::io::copy(&mut (&mut reader).take(range.offset), &mut std::io::sink())?;
+    let mut data = Vec::with_capacity(length as usize);
+    (&mut reader).take(length).read_to_end(&mut data)?;
+    let eof = reader.read(&mut [0])? == 0;
+    let mut out = String::with_capacity(data.len() + 64);
+    out += r#"{"offset":"#;
+    out += &range.offset.to_string();
+    out += r#","length":"#;
+    out += &data.len().to_string();
+    out += r#","eof":"#;
+    out += if eof { "true" } else { "false" };
+    out += r#","data":"#;
+    append_json_string(&mut out, &String::from_utf8_lossy(&data));
+    out += "}";
+    Ok(out)
+}
+
+/// searches the lines of all log files of the save, returns a valid json with the newest matches first
+///
+/// lines without a time, like the ones of a stack trace, are considered written at the time of the line before
+pub fn search(save: &str, search: &Search) -> Result<String, ApiError> {
+    save::exists(save)?;
+    let pattern = if search.regex {
+        search.query.clone()
+    } else {
+        regex::escape(&search.query)
+    };
+    let regex = regex::bytes::RegexBuilder::new(&pattern)
+        .case_insensitive(search.ignore_case)
+        .size_limit(1024 * 1024)
+        .build()
+        .map_err(|error| ApiError::SearchInvalid(error.to_string()))?;
+    let limit = search.limit.unwrap_or(100).clamp(1, MAX_SEARCH_MATCHES);
+    let (from, to) = (search.from.as_deref(), search.to.as_deref());
+    let mut matches = Vec::new();
+    for source in [Source::Manager, Source::Server] {
+        for (name, metadata) in log_files(save, source)? {
+            let modified = metadata.modified()?;
+            // every line was written before the file was last modified
+            if from.is_some_and(|from| format_system_time(modified).as_str() < from) {
+                continue;
+            }
+            let mut times = LineTimes::new(start_date(save, source, &name, modified)?);
+            // only the newest matches of each file can be part of the result
+            let mut found = VecDeque::new();
+            for_each_line(open_log(save, source, &name)?, |offset, line| {
+                let time = times.next(line);
+                if !regex.is_match(line) {
+                    return;
+                }
+                if from.is_some() || to.is_some() {
+                    let Some(time) = time else {
+                        return;
+                    };
+                    if !time_in_range(&time.format(TIME_FORMAT).to_string(), from, to) {
+                        return;
+                    }
+                }
+                if found.len() == limit {
+                    found.pop_front();
+                }
+                let line = &line[..line.len().min(MAX_MATCH_LENGTH)];
+                found.push_back(Match {
+                    source,
+                    file: name.clone(),
+                    offset,
+                    time,
+                    text: String::from_utf8_lossy(line).trim_end().to_owned(),
+                });
+            })?;
+            matches.extend(found);
+        }
+    }
+    matches.sort_by_key(|found| std::cmp::Reverse(found.time));
+    matches.truncate(limit);
+    let mut out = String::with_capacity(256 * matches.len() + 16);
+    out += r#"{"matches":["#;
+    append_comma_separated(matches.iter(), &mut out, |out, found| {
+        *out += r#"{"source":"#;
+        append_json_string(out, found.source.name());
+        *out += r#","file":"#;
+        append_json_string(out, &found.file);
+        *out += r#","offset":"#;
+        *out += &found.offset.to_string();
+        *out += r#","time":"#;
+        match found.time {
+            Some(time) => append_json_string(out, &time.format(TIME_FORMAT).to_string()),
+            None => *out += "null",
+        }
+        *out += r#","text":"#;
+        append_json_string(out, &found.text);
+        *out += "}";
+    });
+    out += "]}";
+    Ok(out)
+}
+
+/// the names and metadata of the log files of a source, which may not exist
+fn log_files(save: &str, source: Source) -> Result<Vec<(String, std::fs::Metadata)>, ApiError> {
+    let entries = match std::fs::read_dir(source.dir(save)) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error.into()),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+            continue;
+        };
+        let metadata = entry.metadata()?;
+        if metadata.is_file() && is_log_file(&name) {
+            files.push((name, metadata));
+        }
+    }
+    Ok(files)
+}
+
+fn is_log_file(name: &str) -> bool {
+    name.ends_with(".log") || name.ends_with(".log.gz")
+}
+
+/// opens a log file for reading, decompressing it if needed
+fn open_log(save: &str, source: Source, file: &str) -> Result<Box<dyn BufRead>, ApiError> {
+    if !is_log_file(file) || file.contains(['/', '\\']) {
+        return Err(ApiError::LogNotFound);
+    }
+    let handle = match File::open(source.dir(save).join(file)) {
+        Ok(handle) => handle,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Err(ApiError::LogNotFound),
+        Err(error) => return Err(error.into()),
+    };
+    let reader = BufReader::new(handle);
+    if file.ends_with(".gz") {
+        Ok(Box::new(BufReader::new(GzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// the day the first lines of a log were written, for the lines that only have the time of day
+fn start_date(
+    save: &str,
+    source: Source,
+    name: &str,
+    modified: SystemTime,
+) -> Result<NaiveDate, ApiError> {
+    // both minecraft and mc-manager start the name of their files with the date
+    let date = name
+        .get(..10)
+        .and_then(|x| NaiveDate::parse_from_str(x, "%Y-%m-%d").ok());
+    if let Some(date) = date {
+        return Ok(date);
+    }
+    // like latest.log, counts back the days from when the file was last written
+    let mut days = 0;
+    let mut last = None;
+    for_each_line(open_log(save, source, name)?, |_, line| {
+        if let Some(Stamp::TimeOfDay(time)) = parse_stamp(line) {
+            if last.is_some_and(|last| time < last) {
+                days += 1;
+            }
+            last = Some(time);
+        }
+    })?;
+    let modified: chrono::DateTime<chrono::Local> = modified.into();
+    Ok(modified.date_naive() - chrono::Days::new(days))
+}
+
+/// calls `f` with where each line starts and the line, without reading the whole file at once
+fn for_each_line(
+    mut reader: impl BufRead,
+    mut f: impl FnMut(usize, &[u8]),
+) -> Result<(), ApiError> {
+    let mut line = Vec::new();
+    let mut offset = 0;
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            return Ok(());
+        }
+        f(offset, &line);
+        offset += read;
+    }
+}
+
+/// tells when each line of a log was written, fed the lines in order
+struct LineTimes {
+    date: NaiveDate,
+    /// the time of day of the last line that had one
+    last: Option<NaiveTime>,
+    current: Option<NaiveDateTime>,
+}
+
+impl LineTimes {
+    fn new(date: NaiveDate) -> Self {
+        LineTimes {
+            date,
+            last: None,
+            current: None,
+        }
+    }
+    /// lines without a stamp get the time of the line before
+    fn next(&mut self, line: &[u8]) -> Option<NaiveDateTime> {
+        match parse_stamp(line) {
+            Some(Stamp::Full(time)) => {
+                self.date = time.date();
+                self.current = Some(time);
+            }
+            Some(Stamp::TimeOfDay(time)) => {
+                // the time went back, a new day started
+                if self.last.is_some_and(|last| time < last) {
+                    self.date = self.date.succ_opt().unwrap_or(self.date);
+                }
+                self.last = Some(time);
+                self.current = Some(self.date.and_time(time));
+            }
+            None => {}
+        }
+        self.current
+    }
+}
+
+fn parse_stamp(line: &[u8]) -> Option<Stamp> {
+    if let Some(time) = line.strip_prefix(b"[").and_then(|x| x.get(..8)) {
+        let time = std::str::from_utf8(time).ok()?;
+        return NaiveTime::parse_from_str(time, "%H:%M:%S")
+            .ok()
+            .map(Stamp::TimeOfDay);
+    }
+    let time = std::str::from_utf8(line.get(..19)?).ok()?;
+    NaiveDateTime::parse_from_str(time, TIME_FORMAT)
+        .ok()
+        .map(Stamp::Full)
+}
@@ -0,0 +1,427 @@
+use crate::loaders::Flavor;
+use crate::properties::read_property;
+use crate::state::save;
+use crate::utils::{append_comma_separated, append_json_string, ApiError};
+use std::fs::File;
+use std::io::{Cursor, ErrorKind, Read};
+use zip::ZipArchive;
+
+/// the biggest jar that can be uploaded
+pub const MAX_MOD_SIZE: u64 = 256 * 1024 * 1024;
+
+/// added to the name of a jar to disable it, the server only loads the files that end in .jar
+const DISABLED_SUFFIX: &str = ".disabled";
+
+/// the biggest metadata file read from a jar
+const MAX_METADATA_SIZE: u64 = 64 * 1024;
+
+/// what a jar says about itself, the fields it does not have are none
+#[derive(Default)]
+struct Metadata {
+    id: Option<String>,
+    name: Option<String>,
+    version: Option<String>,
+    description: Option<String>,
+}
+
+/// returns a valid json with the plugins or mods of the save and what their jars say about them
+pub fn list(save: &str) -> Result<String, ApiError> {
+    let dir = dir(save)?;
+    let mut jars = Vec::new();
+    let entries = match std::fs::read_dir(format!("saves/{save}/{dir}")) {
+        Ok(entries) => entries,
+        // created by the server when it first starts
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(empty(dir)),
+        Err(error) => return Err(error.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let Ok(file) = entry.file_name().into_string() else {
+            continue;
+        };
+        let (file, enabled) = match file.strip_suffix(DISABLED_SUFFIX) {
+            Some(file) => (file.to_owned(), false),
+            None => (file, true),
+        };
+        let metadata = entry.metadata()?;
+        if !file.ends_with(".jar") || !metadata.is_file() {
+            continue;
+        }
+        // a jar that can not be read is still listed, so it can be deleted
+        let info = read_metadata(&entry.path()).unwrap_or_default();
+        jars.push((file, enabled, metadata.len(), info));
+    }
+    jars.sort_by_key(|jar| jar.0.to_lowercase());
+    let mut out = String::with_capacity(256 * jars.len() + 32);
+    out += r#"{"dir":"#;
+    append_json_string(&mut out, dir);
+    out += r#","mods":["#;
+    append_comma_separated(jars.iter(), &mut out, |out, (file, enabled, size, info)| {
+        *out += r#"{"file":"#;
+        append_json_string(out, file);
+        *out += r#","enabled":"#;
+        *out += if *enabled { "true" } else { "false" };
+        *out += r#","size":"#;
+        *out += &size.to_string();
+        for (key, value) in [
+            ("id", &info.id),
+            ("name", &info.name),
+            ("version", &info.version),
+            ("description", &info.description),
+        ] {
+            *out += ",\"";
+            *out += key;
+            *out += "\":";
+            match value {
+                Some(value) => append_json_string(out, value),
+                None => *out += "null",
+            }
+        }
+        *out += "}";
+    });
+    out += "]}";
+    Ok(out)
+}
+
+/// adds a jar to the save, replacing the one with the same name, enabled or not
+pub fn upload(save: &str, file: &str, data: &[u8]) -> Result<(), ApiError> {
+    let dir = dir(save)?;
+    if !file.ends_with(".jar") {
+        return Err(ApiError::ModInvalid);
+    }
+    // refuse anything that is not a jar, the server would fail to load it
+    if ZipArchive::new(Cursor::new(data)).is_err() {
+        return Err(ApiError::ModInvalid);
+    }
+    std::fs::create_dir_all(format!("saves/{save}/{dir}"))?;
+    let path = format!("saves/{save}/{dir}/{file}");
+    let temp = format!("{path}.part");
+    std::fs::write(&temp, data)?;
+    if let Err(error) = std::fs::rename(&temp, &path) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(error.into());
+    }
+    match std::fs::remove_file(format!("{path}{DISABLED_SUFFIX}")) {
+        Err(error) if error.kind() != ErrorKind::NotFound => Err(error.into()),
+        _ => Ok(()),
+    }
+}
+
+/// enables or disables a jar by renaming it, does nothing if it already is
+pub fn set_enabled(save: &str, file: &str, enabled: bool) -> Result<(), ApiError> {
+    if !file.ends_with(".jar") {
+        return Err(ApiError::ModNotFound);
+    }
+    let path = format!("saves/{save}/{}/{file}", dir(save)?);
+    let disabled = format!("{path}{DISABLED_SUFFIX}");
+    let (from, to) = if enabled {
+        (disabled, path)
+    } else {
+        (path, disabled)
+    };
+    match std::fs::metadata(&to) {
+        Ok(_) => return Ok(()),
+        Err(error) if error.kind() != ErrorKind::NotFound => return Err(error.into()),
+        Err(_) => {}
+    }
+    std::fs::rename(from, to).map_err(not_found)
+}
+
+/// removes a jar, enabled or not
+pub fn delete(save: &str, file: &str) -> Result<(), ApiError> {
+    if !file.ends_with(".jar") {
+        return Err(ApiError::ModNotFound);
+    }
+    let path = format!("saves/{save}/{}/{file}", dir(save)?);
+    match std::fs::remove_file(&path) {
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            std::fs::remove_file(format!("{path}{DISABLED_SUFFIX}")).map_err(not_found)
+        }
+        result => result.map_err(ApiError::from),
+    }
+}
+
+/// plugins for paper and purpur, mods for the mod loaders, vanilla servers load neither
+fn dir(save: &str) -> Result<&'static str, ApiError> {
+    save::exists(save)?;
+    let flavor = read_property(
+        format!("saves/{save}/server.properties"),
+        "mc-manager-server-flavor",
+    )?
+    .unwrap_or_default();
+    match Flavor::parse(flavor.trim()) {
+        Some(Flavor::Paper | Flavor::Purpur) => Ok("plugins"),
+        Some(Flavor::Fabric | Flavor::Forge | Flavor::NeoForge) => Ok("mods"),
+        // saves created before flavors are vanilla
+        Some(Flavor::Vanilla) | None => Err(ApiError::FlavorNotSupported),
+    }
+}
+
+fn empty(dir: &str) -> String {
+    let mut out = String::with_capacity(32);
+    out += r#"{"dir":"#;
+    append_json_string(&mut out, dir);
+    out += r#","mods":[]}"#;
+    out
+}
+
+fn not_found(error: std::io::Error) -> ApiError {
+    match error.kind() {
+        ErrorKind::NotFound => ApiError::ModNotFound,
+        _ => error.into(),
+    }
+}
+
+/// reads the metadata of bukkit plugins, fabric mods and forge or neoforge mods, none if the jar has none
+fn read_metadata(path: &std::path::Path) -> Option<Metadata> {
+    let mut archive = ZipArchive::new(File::open(path).ok()?).ok()?;
+    let mut read = |name: &str| -> Option<String> {
+        let file = archive.by_name(name).ok()?;
+        let mut text = String::new();
+        file.take(MAX_METADATA_SIZE)
+            .read_to_string(&mut text)
+            .ok()?;
+        Some(text)
+    };
+    if let Some(text) = read("fabric.mod.json") {
+        return parse_fabric(&text);
+    }
+    for name in ["META-INF/neoforge.mods.toml", "META-INF/mods.toml"] {
+        if let Some(text) = read(name) {
+            let mut metadata = parse_mods_toml(&text);
+            // usually filled in from the manifest when the jar is built
+            if metadata.version.as_deref().is_none_or(|x| x.contains("${")) {
+                metadata.version = read("META-INF/MANIFEST.MF").and_then(|manifest| {
+                    manifest.lines().find_map(|line| {
+                        let version = line.strip_prefix("Implementation-Version:")?;
+                        Some(version.trim().to_owned())
+                    })
+                });
+            }
+            return Some(metadata);
+        }
+    }
+    for name in ["paper-plugin.yml", "plugin.yml"] {
+        if let Some(text) = read(name) {
+            return Some(parse_plugin_yml(&text));
+        }
+    }
+    None
+}
+
+fn parse_fabric(text: &str) -> Option<Metadata> {
+    let json: serde_json::Value = serde_json::from_str(text).ok()?;
+    let field = |name: &str| json.get(name)?.as_str().map(str::to_owned);
+    Some(Metadata {
+        id: field("id"),
+        name: field("name"),
+        version: field("version"),
+        description: field("description"),
+    })
+}
+
+/// only the top level keys with a value in the same line are read, which are the ones needed
+fn parse_plugin_yml(text: &str) -> Metadata {
+    let mut metadata = Metadata::default();
+    for line in text.lines() {
+        if line.starts_with([' ', '\t', '#', '-']) {
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|x| x.strip_suffix('"'))
+            .or_else(|| value.strip_prefix('\'').and_then(|x| x.strip_suffix('\'')))
+            .unwrap_or(value);
+        if value.is_empty() || value == "|" || value == ">" {
+            continue;
+        }
+        let field = match key.trim() {
+            "name" => &mut metadata.name,
+            "version" => &mut metadata.version,
+            "description" => &mut metadata.description,
+            _ => continue,
+        };
+        *field = Some(value.to_owned());
+    }
+    metadata.id = metadata.name.clone();
+    metadata
+}
+
+/// reads the first mod of the `[[mods]]` tables, strings can be quoted or multiline
+fn parse_mods_toml(text: &str) -> Metadata {
+    let mut metadata = Metadata::default();
+    let mut lines = text.lines();
+    let mut in_mods = false;
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            if in_mods {
+                break;
+            }
+            in_mods = line == "[[mods]]";
+            continue;
+        }
+        if !in_mods {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        let value = if let Some(rest) = value
+            .strip_prefix("'''")
+            .or_else(|| value.strip_prefix("\"\"\""))
+        {
+            let quote = &value[..3];
+            let mut out = String::new();
+            let mut rest = rest.to_owned();
+            loop {
+                if let Some(end) = rest.find(quote) {
+                    out += &rest[..end];
+                    break;
+                }
+                out += &rest;
+                match lines.next() {
+                    Some(next) => {
+                        out.push('\n');
+                        rest = next.to_owned();
+                    }
+                    None => break,
+                }
+            }
+            out.trim().to_owned()
+        } else if let Some(rest) = value.strip_prefix(['"', '\'']) {
+            let quote = &value[..1];
+            match rest.find(quote) {
+                Some(end) => rest[..end].to_owned(),
+                None => continue,
+            }
+        } else {
+            continue;
+        };
+        let field = match key.trim() {
+            "modId" => &mut metadata.id,
+            "displayName" => &mut metadata.name,
+            "version" => &mut metadata.version,
+            "description" => &mut metadata.description,
+            _ => continue,
+        };
+        *field = Some(value);
+    }
+    metadata
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(metadata: Metadata) -> [Option<String>; 4] {
+        [
+            metadata.id,
+            metadata.name,
+            metadata.version,
+            metadata.description,
+        ]
+    }
+
+    fn some(values: [&str; 4]) -> [Option<String>; 4] {
+        values.map(|x| Some(x.to_owned()))
+    }
+
+    #[test]
+    fn plugin_yml() {
+        let text = "# the main class\n\
+            main: com.example.Essentials\n\
+            name: Essentials\n\
+            version: '2.20.1'\n\
+            description: \"Provides essential commands\"\n\
+            commands:\n  \
+              home:\n    \
+                description: Teleports you home\n\
+            authors:\n\
+            - someone\n";
+        assert_eq!(
+            fields(parse_plugin_yml(text)),
+            some([
+                "Essentials",
+                "Essentials",
+                "2.20.1",
+                "Provides essential commands"
+            ])
+        );
+    }
+
+    #[test]
+    fn plugin_yml_without_fields() {
+        let text = "name: Example\r\ndescription: |\r\n  multiline\r\nversion:\r\n";
+        let metadata = parse_plugin_yml(text);
+        assert_eq!(metadata.id.as_deref(), Some("Example"));
+        assert_eq!(metadata.name.as_deref(), Some("Example"));
+        assert_eq!(metadata.version, None);
+        assert_eq!(metadata.description, None);
+        assert_eq!(fields(parse_plugin_yml("")), [None, None, None, None]);
+    }
+
+    #[test]
+    fn mods_toml() {
+        let text = r#"
+modLoader="javafml"
+loaderVersion="[47,)"
+license="MIT"
+
+[[mods]]
+modId="examplemod" # a comment
+version="1.0.0"
+displayName = 'Example Mod'
+description='''
+Adds examples.
+On two lines.
+'''
+
+[[dependencies.examplemod]]
+modId="forge"
+version="[47,)"
+"#;
+        assert_eq!(
+            fields(parse_mods_toml(text)),
+            some([
+                "examplemod",
+                "Example Mod",
+                "1.0.0",
+                "Adds examples.\nOn two lines."
+            ])
+        );
+    }
+
+    #[test]
+    fn mods_toml_only_reads_the_first_mod() {
+        let text = "[[mods]]\nmodId=\"first\"\nversion=\"${file.jarVersion}\"\n\
+            [[mods]]\nmodId=\"second\"\ndisplayName=\"Second\"\n";
+        let metadata = parse_mods_toml(text);
+        assert_eq!(metadata.id.as_deref(), Some("first"));
+        assert_eq!(metadata.version.as_deref(), Some("${file.jarVersion}"));
+        assert_eq!(metadata.name, None);
+        // an unterminated multiline string ends with the file
+        let metadata = parse_mods_toml("[[mods]]\ndescription=\"\"\"\nnever ends");
+        assert_eq!(metadata.description.as_deref(), Some("never ends"));
+        assert_eq!(
+            fields(parse_mods_toml("modId=\"outside\"")),
+            [None, None, None, None]
+        );
+    }
+
+    #[test]
+    fn fabric_mod_json() {
+        let text = r#"{"schemaVersion": 1, "id": "sodium", "version": "0.5.8",
+            "name": "Sodium", "description": "A rendering engine", "authors": ["JellySquid"]}"#;
+        assert_eq!(
+            fields(parse_fabric(text).unwrap()),
+            some(["sodium", "Sodium", "0.5.8", "A rendering engine"])
+        );
+        assert!(parse_fabric("not json").is_none());
+    }
+}
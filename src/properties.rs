@@ -60,7 +60,7 @@ impl PropValue {
     }
 }
 
-pub const CREATE_PROPERTIES: &[&'static str] = &[
+pub const CREATE_PROPERTIES: &[&str] = &[
     "motd",
     "level-seed",
     "gamemode",
@@ -74,6 +74,9 @@ pub const CREATE_PROPERTIES: &[&'static str] = &[
     "level-type",
 ];
 
+/// the properties only admins can change, as they choose the code that runs on the machine
+pub const ADMIN_PROPERTIES: &[&str] = &["mc-manager-server-jar"];
+
 pub const PROPERTIES: &[PropDef] = &[
     PropDef {
         access: PropAccess::Write,
@@ -285,6 +288,20 @@ pub const PROPERTIES: &[PropDef] = &[
         label: "Versão do servidor",
         desc: "A variable for mc-manager, to keep track of what server version this is.",
     },
+    PropDef {
+        access: PropAccess::Read,
+        ty: PropType::StrEnum(0, &[("vanilla", "Vanilla"), ("paper", "Paper"), ("purpur", "Purpur"), ("fabric", "Fabric"), ("forge", "Forge"), ("neoforge", "NeoForge")]),
+        name: "mc-manager-server-flavor",
+        label: "Tipo do servidor",
+        desc: "A variable for mc-manager, to keep track of the server software chosen when this save was created.",
+    },
+    PropDef {
+        access: PropAccess::Read,
+        ty: PropType::String(""),
+        name: "mc-manager-server-build",
+        label: "Build do servidor",
+        desc: "A variable for mc-manager, to keep track of the build of the server software, empty for vanilla.",
+    },
     PropDef {
         access: PropAccess::Read,
         ty: PropType::Datetime,
@@ -299,6 +316,83 @@ pub const PROPERTIES: &[PropDef] = &[
         label: "Tempo do pu accesso",
         desc: "A variable for mc-manager, to keep track when this save was last online.",
     },
+    PropDef {
+        access: PropAccess::Write,
+        ty: PropType::StrEnum(0, &[("never", "Nunca"), ("on-failure", "Quando travar"), ("always", "Sempre")]),
+        name: "mc-manager-restart-policy",
+        label: "Reiniciar automaticamente",
+        desc: "A variable for mc-manager, decides if the server is started again after the java process exits without being stopped by mc-manager. never - the server stays offline. on-failure - the server is restarted only if it crashed (exited with a non zero status). always - the server is restarted whenever it exits on its own.",
+    },
+    PropDef {
+        access: PropAccess::Write,
+        ty: PropType::Uint(3, 0, 100),
+        name: "mc-manager-restart-max-retries",
+        label: "Máximo de reinícios seguidos",
+        desc: "A variable for mc-manager, the maximum number of consecutive automatic restarts, the count is reset when the server finishes loading.",
+    },
+    PropDef {
+        access: PropAccess::Write,
+        ty: PropType::Uint(10, 0, 3600),
+        name: "mc-manager-restart-backoff",
+        label: "Espera antes de reiniciar",
+        desc: "A variable for mc-manager, the number of seconds to wait before the first automatic restart, each consecutive restart waits twice as long as the previous one.",
+    },
+    PropDef {
+        access: PropAccess::Write,
+        ty: PropType::Uint(10, 1, 1024),
+        name: "mc-manager-log-max-size",
+        label: "Tamanho máximo de cada log",
+        desc: "A variable for mc-manager, the size in megabytes at which the console log in mc-manager-logs is continued in a new file, a new file is also started every day and every time the server starts.",
+    },
+    PropDef {
+        access: PropAccess::Write,
+        ty: PropType::Uint(14, 1, 3650),
+        name: "mc-manager-log-retention",
+        label: "Dias para manter os logs",
+        desc: "A variable for mc-manager, the number of days console logs in mc-manager-logs are kept after they were last written.",
+    },
+    PropDef {
+        access: PropAccess::Write,
+        ty: PropType::Uint(0, 0, 1048576),
+        name: "mc-manager-memory-min",
+        label: "Memória inicial (MB)",
+        desc: "A variable for mc-manager, the initial heap size of the java process in megabytes, passed as -Xms. 0 leaves it to the default of the JVM.",
+    },
+    PropDef {
+        access: PropAccess::Write,
+        ty: PropType::Uint(0, 0, 1048576),
+        name: "mc-manager-memory-max",
+        label: "Memória máxima (MB)",
+        desc: "A variable for mc-manager, the maximum heap size of the java process in megabytes, passed as -Xmx. 0 leaves it to the default of the JVM, which is usually a quarter of the memory of the machine.",
+    },
+    PropDef {
+        access: PropAccess::Write,
+        ty: PropType::String(""),
+        name: "mc-manager-jvm-args",
+        label: "Argumentos da JVM",
+        desc: "A variable for mc-manager, extra options for the JVM separated by spaces, like Aikar's flags. Only -Xms, -Xmx, -Xmn, -Xss, the -XX options of the memory and the garbage collectors, and -D properties not read by java, log4j or the mod loaders are accepted.",
+    },
+    PropDef {
+        access: PropAccess::Write,
+        ty: PropType::String(""),
+        name: "mc-manager-server-args",
+        label: "Argumentos do servidor",
+        desc: "A variable for mc-manager, extra arguments for the server separated by spaces, passed after nogui.",
+    },
+    PropDef {
+        access: PropAccess::Write,
+        ty: PropType::String(""),
+        name: "mc-manager-java",
+        label: "Versão do Java",
+        desc: "A variable for mc-manager, the java runtime the server runs with. Empty picks the oldest runtime found that the server version supports, a number like 17 picks a runtime of that major version, anything else must be the path of one of the runtimes found by mc-manager.",
+    },
+    PropDef {
+        access: PropAccess::Write,
+        ty: PropType::String("server.jar"),
+        name: "mc-manager-server-jar",
+        label: "Arquivo do servidor",
+        desc: "A variable for mc-manager, the name of the jar in the save folder that is run, like the one installed by a mod loader. Only admins can change it.",
+    },
     PropDef {
         access: PropAccess::Write,
         ty: PropType::String("Um servidor de minecraft, gerenciando pelo mc-manager"),
@@ -587,10 +681,117 @@ pub fn write_properties(
     Ok(())
 }
 
+/// checks the string properties that mc-manager passes to the java process
+pub fn valid_string(name: &str, value: &str) -> bool {
+    match name {
+        "mc-manager-jvm-args" => value.split_whitespace().all(valid_jvm_arg),
+        "mc-manager-server-args" | "mc-manager-java" => !value.contains(char::is_control),
+        "mc-manager-server-jar" => {
+            value.ends_with(".jar")
+                && !value.starts_with('.')
+                && !value.contains(['/', '\\', ':'])
+                && !value.contains(char::is_control)
+        }
+        _ => true,
+    }
+}
+
+/// only the options that tune the memory and the garbage collector are accepted, and system properties
+/// that are not read by java itself, the loggers or the mod loaders, as editing a save must not give
+/// access to the machine, options like -Xlog or -XX:ErrorFile write files and others load classes
+fn valid_jvm_arg(arg: &str) -> bool {
+    /// the -XX options accepted, with + or - if they are flags, or with = and a number
+    const OPTIONS: &[&str] = &[
+        "AlwaysPreTouch",
+        "ConcGCThreads",
+        "DisableExplicitGC",
+        "G1HeapRegionSize",
+        "G1HeapWastePercent",
+        "G1MaxNewSizePercent",
+        "G1MixedGCCountTarget",
+        "G1MixedGCLiveThresholdPercent",
+        "G1NewSizePercent",
+        "G1RSetUpdatingPauseTimePercent",
+        "G1ReservePercent",
+        "InitialRAMPercentage",
+        "InitiatingHeapOccupancyPercent",
+        "MaxGCPauseMillis",
+        "MaxMetaspaceSize",
+        "MaxRAMPercentage",
+        "MaxTenuringThreshold",
+        "MetaspaceSize",
+        "MinRAMPercentage",
+        "ParallelGCThreads",
+        "ParallelRefProcEnabled",
+        "PerfDisableSharedMem",
+        "ReservedCodeCacheSize",
+        "SurvivorRatio",
+        "UnlockDiagnosticVMOptions",
+        "UnlockExperimentalVMOptions",
+        "UseCompressedOops",
+        "UseG1GC",
+        "UseLargePages",
+        "UseNUMA",
+        "UseParallelGC",
+        "UseShenandoahGC",
+        "UseStringDeduplication",
+        "UseTransparentHugePages",
+        "UseZGC",
+        "ZGenerational",
+    ];
+    /// compared in lower case, log4j also reads its properties in other cases
+    const REFUSED_PROPERTIES: &[&str] = &[
+        "java",
+        "jdk.",
+        "sun.",
+        "com.sun.",
+        "log4j",
+        "fabric.",
+        "fml.",
+        "forge.",
+        "neoforge.",
+        "legacyclasspath",
+        "librarydirectory",
+        "ignorelist",
+        "mergemodules",
+    ];
+    if arg.contains(char::is_control) {
+        return false;
+    }
+    if let Some(size) = ["-Xms", "-Xmx", "-Xmn", "-Xss"]
+        .iter()
+        .find_map(|prefix| arg.strip_prefix(prefix))
+    {
+        return is_jvm_number(size);
+    }
+    if let Some(option) = arg.strip_prefix("-XX:") {
+        return match option.split_once('=') {
+            Some((name, value)) => OPTIONS.contains(&name) && is_jvm_number(value),
+            None => option
+                .strip_prefix(['+', '-'])
+                .is_some_and(|name| OPTIONS.contains(&name)),
+        };
+    }
+    if let Some(property) = arg.strip_prefix("-D") {
+        let key = property.split_once('=').map_or(property, |(key, _)| key);
+        let key = key.to_ascii_lowercase();
+        return !key.is_empty() && !REFUSED_PROPERTIES.iter().any(|x| key.starts_with(x));
+    }
+    false
+}
+
+/// like 512, 4096M or 8g
+fn is_jvm_number(value: &str) -> bool {
+    let digits = value
+        .strip_suffix(['k', 'K', 'm', 'M', 'g', 'G'])
+        .unwrap_or(value);
+    !digits.is_empty() && digits.bytes().all(|x| x.is_ascii_digit())
+}
+
 /// validates that the client can write to these properties, returns first error, if any
 pub fn validate_properties(values: &HashMap<String, PropValue>) -> Result<(), ApiError> {
     for (key, value) in values.iter() {
-        if let Some(prop) = PROPERTIES.iter().filter(|prop| prop.name == key).next() {
+        if let Some(prop) = PROPERTIES.iter().find(|prop| prop.name == key) {
             if prop.access == PropAccess::Write {
                 match &prop.ty {
                     PropType::Bool(_) => {
@@ -599,8 +800,10 @@ pub fn validate_properties(values: &HashMap<String, PropValue>) -> Result<(), Ap
                         }
                     }
                     PropType::String(_) => {
-                        if let PropValue::String(_) = value {
-                            continue;
+                        if let PropValue::String(value) = value {
+                            if valid_string(key, value) {
+                                continue;
+                            }
                         }
                     }
                     PropType::Int(_, min, max) => {
@@ -631,13 +834,14 @@ pub fn validate_properties(values: &HashMap<String, PropValue>) -> Result<(), Ap
                     }
                     PropType::Datetime => {
                         if let PropValue::String(value) = value {
-                            if value.len() == 19 {
-                                if let Ok(_) = chrono::NaiveDateTime::parse_from_str(
+                            if value.len() == 19
+                                && chrono::NaiveDateTime::parse_from_str(
                                     value,
                                     "%Y-%m-%d %H:%M:%S",
-                                ) {
-                                    continue;
-                                }
+                                )
+                                .is_ok()
+                            {
+                                continue;
                             }
                         }
                     }
@@ -688,7 +892,7 @@ pub fn generate_properties(version: &str, values: &HashMap<String, PropValue>) -
             match &prop.ty {
                 PropType::Bool(true) => out += "true",
                 PropType::Bool(false) => out += "false",
-                PropType::String(value) => append_prop_escaped(&mut out, *value),
+                PropType::String(value) => append_prop_escaped(&mut out, value),
                 PropType::Int(value, _, _) => out += &value.to_string(),
                 PropType::Uint(value, _, _) => out += &value.to_string(),
                 PropType::Datetime => out += &now,
@@ -785,3 +989,74 @@ pub fn parse_prop_unescaped(value: &str) -> String {
         out
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepted_jvm_args() {
+        // the flags of aikar, which most server hosts use
+        for arg in [
+            "-Xms4G",
+            "-Xmx4096M",
+            "-Xss1m",
+            "-XX:+UseG1GC",
+            "-XX:+ParallelRefProcEnabled",
+            "-XX:MaxGCPauseMillis=200",
+            "-XX:+UnlockExperimentalVMOptions",
+            "-XX:-UseLargePages",
+            "-XX:G1HeapRegionSize=8M",
+            "-Dusing.aikars.flags=https://mcflags.emc.gs",
+            "-Daikars.new.flags=true",
+            "-Dfile.encoding=UTF-8",
+            "-Dpaper.disableChannelLimit",
+        ] {
+            assert!(valid_jvm_arg(arg), "{arg}");
+        }
+    }
+
+    #[test]
+    fn refused_jvm_args() {
+        for arg in [
+            "",
+            "-Xmx",
+            "-Xmx4T",
+            "-Xmx-1G",
+            "-Xlog:gc:file=/etc/passwd",
+            "-XX:ErrorFile=/tmp/x",
+            "-XX:OnOutOfMemoryError=sh",
+            "-XX:+HeapDumpOnOutOfMemoryError",
+            "-XX:UseG1GC",
+            "-XX:MaxGCPauseMillis=a",
+            "-javaagent:agent.jar",
+            "-agentpath:/lib/agent.so",
+            "-cp",
+            "-jar",
+            "--add-opens",
+            "@args.txt",
+            "-D",
+            "-Djava.library.path=/tmp",
+            "-DJAVA.home=/tmp",
+            "-Djdk.attach.allowAttachSelf=true",
+            "-Dlog4j.configurationFile=http://example.com",
+            "-DLOG4J2.formatMsgNoLookups=false",
+            "-Dfabric.addMods=/tmp/mod.jar",
+            "-Dfml.ignoreInvalidMinecraftCertificates=true",
+            "-Dlegacyclasspath=/tmp",
+            "-Dfile.encoding=UTF-8\n-jar",
+        ] {
+            assert!(!valid_jvm_arg(arg), "{arg:?}");
+        }
+    }
+
+    #[test]
+    fn jvm_numbers() {
+        for value in ["0", "512", "4096M", "8g", "64k"] {
+            assert!(is_jvm_number(value), "{value}");
+        }
+        for value in ["", "M", "1.5G", "4GB", " 4G", "-1"] {
+            assert!(!is_jvm_number(value), "{value}");
+        }
+    }
+}
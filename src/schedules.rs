@@ -0,0 +1,458 @@
+use crate::instances::{
+    get_stop_timeout, query_instance, start_instance, stop_instance, write_instance,
+    InstanceStatus,
+};
+use crate::server::is_shutdown;
+use crate::state::{backup, save};
+use crate::utils::{now, ApiError};
+use chrono::{Datelike, Timelike};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    static ref SCHEDULES: std::sync::Mutex<ScheduleFile> =
+        std::sync::Mutex::new(ScheduleFile::default());
+}
+
+const SCHEDULES_FILE: &str = "schedules.json";
+
+/// the contents of the schedules file
+#[derive(Default, Serialize, Deserialize)]
+struct ScheduleFile {
+    /// the id of the next schedule created, ids are never reused, even after a schedule is deleted
+    next_id: u64,
+    schedules: Vec<Schedule>,
+}
+
+/// the schedules file, as it is now or as written before it had `next_id`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredSchedules {
+    File(ScheduleFile),
+    List(Vec<Schedule>),
+}
+
+/// a task that runs on a save whenever its cron expression matches the current minute
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    id: u64,
+    save: String,
+    /// five fields: minute, hour, day of month, month and day of week, or one of @hourly, @daily, @weekly and @monthly
+    cron: String,
+    action: ScheduleAction,
+    enabled: bool,
+    /// when the schedule last ran, empty if never
+    #[serde(default)]
+    last_run: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ScheduleAction {
+    /// starts the server if it is not running
+    Start,
+    /// stops the server if it is running
+    Stop,
+    /// stops and starts the server again, does nothing if it is not running
+    Restart,
+    /// makes a backup, removing the oldest automatic backups so that at most `retention` of them remain
+    Backup { retention: Option<usize> },
+    /// sends a command, in the same format accepted by the console, does nothing if the server is not online
+    Command { command: String },
+}
+
+/// a parsed cron expression, each field is a bit mask of the values that match
+struct Cron {
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    /// true if the day of month field starts with *, see `Cron::matches`
+    any_day: bool,
+    /// true if the day of week field starts with *, see `Cron::matches`
+    any_weekday: bool,
+}
+
+impl Cron {
+    fn parse(text: &str) -> Option<Self> {
+        let text = match text.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            text => text,
+        };
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return None;
+        };
+        // 7 is also sunday
+        let weekdays_mask = parse_cron_field(weekdays, 0, 7)?;
+        Some(Cron {
+            minutes: parse_cron_field(minutes, 0, 59)?,
+            hours: parse_cron_field(hours, 0, 23)? as u32,
+            days: parse_cron_field(days, 1, 31)? as u32,
+            months: parse_cron_field(months, 1, 12)? as u16,
+            weekdays: ((weekdays_mask | (weekdays_mask >> 7)) & 0x7F) as u8,
+            any_day: days.starts_with('*'),
+            any_weekday: weekdays.starts_with('*'),
+        })
+    }
+    /// like in cron, if both day fields are restricted, matching either one is enough
+    fn matches(&self, time: &chrono::DateTime<chrono::Local>) -> bool {
+        let day = self.days & (1 << time.day()) != 0;
+        let weekday = self.weekdays & (1 << time.weekday().num_days_from_sunday()) != 0;
+        let day = match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        };
+        day && self.minutes & (1 << time.minute()) != 0
+            && self.hours & (1 << time.hour()) != 0
+            && self.months & (1 << time.month()) != 0
+    }
+}
+
+/// parses one field of a cron expression, like `*`, `5`, `1-5`, `*/15` or `0,30`, into a bit mask
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step.parse::<u32>().ok().filter(|x| *x > 0)?)),
+            None => (part, None),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (start.parse().ok()?, end.parse().ok()?)
+        } else {
+            let value = range.parse().ok()?;
+            // a single value with a step means from that value to the max
+            (value, if step.is_some() { max } else { value })
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Some(mask)
+}
+
+/// reads the schedules file, if it exists, must be called before `run_scheduler`
+pub fn load() -> Result<(), String> {
+    let stored = match std::fs::read_to_string(SCHEDULES_FILE) {
+        Ok(text) => serde_json::from_str(&text).map_err(|error| error.to_string())?,
+        Err(error) if error.kind() == ErrorKind::NotFound => StoredSchedules::List(Vec::new()),
+        Err(error) => return Err(error.to_string()),
+    };
+    let mut file = match stored {
+        StoredSchedules::File(file) => file,
+        StoredSchedules::List(schedules) => ScheduleFile {
+            next_id: 0,
+            schedules,
+        },
+    };
+    if let Some(schedule) = file.schedules.iter().find(|x| Cron::parse(&x.cron).is_none()) {
+        return Err(format!("schedule {} has an invalid cron expression", schedule.id));
+    }
+    // in case the file was edited by hand
+    let max = file.schedules.iter().map(|x| x.id).max().unwrap_or(0);
+    file.next_id = file.next_id.max(max + 1);
+    *SCHEDULES.lock().expect("SCHEDULES lock is poisoned") = file;
+    Ok(())
+}
+
+/// returns a valid json with all schedules
+pub fn list(visible: impl Fn(&str) -> bool) -> String {
+    let file = SCHEDULES.lock().expect("SCHEDULES lock is poisoned");
+    let schedules: Vec<&Schedule> = file.schedules.iter().filter(|x| visible(&x.save)).collect();
+    let mut out = String::with_capacity(4 * 1024);
+    out += r#"{"schedules":"#;
+    out += &serde_json::to_string(&schedules).expect("schedules are always serializable");
+    out += "}";
+    out
+}
+
+/// creates a new schedule, returns its id
+pub fn create(
+    save: &str,
+    cron: String,
+    action: ScheduleAction,
+    enabled: bool,
+) -> Result<u64, ApiError> {
+    save::exists(save)?;
+    validate(&cron, &action)?;
+    let mut file = SCHEDULES.lock().expect("SCHEDULES lock is poisoned");
+    let id = file.next_id;
+    file.next_id += 1;
+    file.schedules.push(Schedule {
+        id,
+        save: save.to_owned(),
+        cron,
+        action,
+        enabled,
+        last_run: String::new(),
+    });
+    write(&file)?;
+    Ok(id)
+}
+
+/// returns the save the schedule belongs to
+pub fn save_of(id: u64) -> Result<String, ApiError> {
+    let file = SCHEDULES.lock().expect("SCHEDULES lock is poisoned");
+    match file.schedules.iter().find(|x| x.id == id) {
+        Some(schedule) => Ok(schedule.save.clone()),
+        None => Err(ApiError::ScheduleNotFound),
+    }
+}
+
+/// modifies the fields of a schedule that are `Some`
+pub fn modify(
+    id: u64,
+    cron: Option<String>,
+    action: Option<ScheduleAction>,
+    enabled: Option<bool>,
+) -> Result<(), ApiError> {
+    let mut file = SCHEDULES.lock().expect("SCHEDULES lock is poisoned");
+    let Some(schedule) = file.schedules.iter_mut().find(|x| x.id == id) else {
+        return Err(ApiError::ScheduleNotFound);
+    };
+    validate(
+        cron.as_deref().unwrap_or(&schedule.cron),
+        action.as_ref().unwrap_or(&schedule.action),
+    )?;
+    if let Some(cron) = cron {
+        schedule.cron = cron;
+    }
+    if let Some(action) = action {
+        schedule.action = action;
+    }
+    if let Some(enabled) = enabled {
+        schedule.enabled = enabled;
+    }
+    write(&file)
+}
+
+/// deletes a schedule
+pub fn delete(id: u64) -> Result<(), ApiError> {
+    let mut file = SCHEDULES.lock().expect("SCHEDULES lock is poisoned");
+    let Some(index) = file.schedules.iter().position(|x| x.id == id) else {
+        return Err(ApiError::ScheduleNotFound);
+    };
+    file.schedules.remove(index);
+    write(&file)
+}
+
+/// deletes all schedules of a save, used when the save is deleted
+pub fn delete_all(save: &str) -> Result<(), ApiError> {
+    let mut file = SCHEDULES.lock().expect("SCHEDULES lock is poisoned");
+    let count = file.schedules.len();
+    file.schedules.retain(|x| x.save != save);
+    if file.schedules.len() != count {
+        write(&file)?;
+    }
+    Ok(())
+}
+
+fn validate(cron: &str, action: &ScheduleAction) -> Result<(), ApiError> {
+    if Cron::parse(cron).is_none() {
+        return Err(ApiError::ScheduleInvalid("cron".to_owned()));
+    }
+    if let ScheduleAction::Command { command } = action {
+        if command.trim().is_empty() || command.bytes().any(|x| matches!(x, 0..=31 | 127)) {
+            return Err(ApiError::ScheduleInvalid("command".to_owned()));
+        }
+    }
+    Ok(())
+}
+
+fn write(file: &ScheduleFile) -> Result<(), ApiError> {
+    let text = serde_json::to_string_pretty(file).expect("schedules are always serializable");
+    // write to a temporary file first, so the schedules are never lost halfway
+    let temp = format!("{SCHEDULES_FILE}.tmp");
+    std::fs::write(&temp, text)?;
+    std::fs::rename(&temp, SCHEDULES_FILE)?;
+    Ok(())
+}
+
+/// runs the schedules every minute until mc-manager shuts down
+pub async fn run_scheduler() {
+    println!("[*] Scheduler started");
+    let mut last_minute = None;
+    while !is_shutdown() {
+        let time = chrono::Local::now();
+        let minute = time.with_second(0).and_then(|x| x.with_nanosecond(0));
+        if minute != last_minute {
+            last_minute = minute;
+            let due: Vec<Schedule> = {
+                let mut file = SCHEDULES.lock().expect("SCHEDULES lock is poisoned");
+                let mut due = Vec::new();
+                for schedule in file.schedules.iter_mut() {
+                    if schedule.enabled && Cron::parse(&schedule.cron).is_some_and(|x| x.matches(&time)) {
+                        schedule.last_run = now();
+                        due.push(schedule.clone());
+                    }
+                }
+                if !due.is_empty() && write(&file).is_err() {
+                    println!("[!] Failed to write {SCHEDULES_FILE}");
+                }
+                due
+            };
+            for schedule in due {
+                tokio::spawn(run_schedule(schedule));
+            }
+        }
+        // wake up shortly after the start of the next minute
+        let wait = 60 - time.second() as u64;
+        tokio::time::sleep(Duration::from_secs(wait.clamp(1, 60))).await;
+    }
+    println!("[*] Scheduler finished");
+}
+
+async fn run_schedule(schedule: Schedule) {
+    let name = schedule.save.as_str();
+    let result = match &schedule.action {
+        ScheduleAction::Start => match query_instance(name).await {
+            Ok(InstanceStatus::Offline | InstanceStatus::Crashed | InstanceStatus::Cold) => {
+                start_instance(name).await
+            }
+            Ok(_) => Ok(()),
+            Err(error) => Err(error),
+        },
+        ScheduleAction::Stop => match query_instance(name).await {
+            Ok(InstanceStatus::Online) => stop_instance(name).await,
+            Ok(_) => Ok(()),
+            Err(error) => Err(error),
+        },
+        ScheduleAction::Restart => match query_instance(name).await {
+            Ok(InstanceStatus::Online) => restart(name).await,
+            Ok(_) => Ok(()),
+            Err(error) => Err(error),
+        },
+        ScheduleAction::Backup { retention } => {
+            let job = crate::jobs::create("backup", name);
+            let result = crate::jobs::run_backup(name, true, job).await;
+            crate::jobs::finish(job, result.clone().map(Some));
+            match (result, retention) {
+                (Ok(_), Some(retention)) => backup::prune(name, *retention),
+                (result, _) => result.map(|_| ()),
+            }
+        }
+        ScheduleAction::Command { command } => match query_instance(name).await {
+            Ok(InstanceStatus::Online) => write_instance(name, command).await,
+            Ok(_) => Ok(()),
+            Err(error) => Err(error),
+        },
+    };
+    match result {
+        Ok(()) => println!("[{name}] Schedule {} finished", schedule.id),
+        Err(ApiError::IOError(error) | ApiError::JavaError(error)) => {
+            println!("[!] Schedule {} of save \"{name}\" failed: {error}", schedule.id)
+        }
+        Err(_) => println!("[!] Schedule {} of save \"{name}\" failed", schedule.id),
+    }
+}
+
+/// stops the server, waits for it to exit, and starts it again
+async fn restart(name: &str) -> Result<(), ApiError> {
+    stop_instance(name).await?;
+    let start = Instant::now();
+    let timeout = get_stop_timeout() + Duration::from_secs(15);
+    loop {
+        match query_instance(name).await? {
+            InstanceStatus::Offline | InstanceStatus::Crashed => break,
+            status if start.elapsed() >= timeout => return Err(status.to_error()),
+            _ => tokio::time::sleep(Duration::from_secs(1)).await,
+        }
+    }
+    if is_shutdown() {
+        return Ok(());
+    }
+    start_instance(name).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// the values matched by a field
+    fn values(field: &str, min: u32, max: u32) -> Vec<u32> {
+        let mask = parse_cron_field(field, min, max).unwrap();
+        (min..=max).filter(|x| mask & (1 << x) != 0).collect()
+    }
+
+    fn at(day: u32, hour: u32, minute: u32) -> chrono::DateTime<chrono::Local> {
+        // march of 2024 starts on a friday
+        chrono::Local
+            .with_ymd_and_hms(2024, 3, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_fields() {
+        assert_eq!(values("*", 0, 5), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(values("5", 0, 59), [5]);
+        assert_eq!(values("1-3", 1, 12), [1, 2, 3]);
+        assert_eq!(values("*/15", 0, 59), [0, 15, 30, 45]);
+        assert_eq!(values("0,30", 0, 59), [0, 30]);
+        assert_eq!(values("1-10/4", 0, 59), [1, 5, 9]);
+        assert_eq!(values("20/1", 0, 23), [20, 21, 22, 23]);
+        assert_eq!(values("5/10", 0, 23), [5, 15]);
+        assert_eq!(values("59", 0, 59), [59]);
+    }
+
+    #[test]
+    fn refuses_invalid_fields() {
+        for field in ["", "a", "0", "13", "5-1", "1-13", "*/0", "*/", "1-", ","] {
+            assert!(parse_cron_field(field, 1, 12).is_none(), "{field}");
+        }
+    }
+
+    #[test]
+    fn refuses_invalid_expressions() {
+        for text in ["", "* * * *", "* * * * * *", "@yearly", "0 24 * * *"] {
+            assert!(Cron::parse(text).is_none(), "{text}");
+        }
+    }
+
+    #[test]
+    fn matches_times() {
+        let cron = Cron::parse("30 4 * * *").unwrap();
+        assert!(cron.matches(&at(1, 4, 30)));
+        assert!(!cron.matches(&at(1, 4, 31)));
+        assert!(!cron.matches(&at(1, 5, 30)));
+        let cron = Cron::parse("@hourly").unwrap();
+        assert!(cron.matches(&at(9, 13, 0)));
+        assert!(!cron.matches(&at(9, 13, 1)));
+        let cron = Cron::parse("0 0 * 4 *").unwrap();
+        assert!(!cron.matches(&at(1, 0, 0)));
+    }
+
+    #[test]
+    fn matches_days() {
+        // only fridays
+        let cron = Cron::parse("0 12 * * 5").unwrap();
+        assert!(cron.matches(&at(1, 12, 0)));
+        assert!(!cron.matches(&at(2, 12, 0)));
+        // 7 is also sunday
+        let cron = Cron::parse("0 12 * * 7").unwrap();
+        assert!(cron.matches(&at(3, 12, 0)));
+        // either the 2nd or a friday, when both fields are restricted
+        let cron = Cron::parse("0 12 2 * 5").unwrap();
+        assert!(cron.matches(&at(1, 12, 0)));
+        assert!(cron.matches(&at(2, 12, 0)));
+        assert!(!cron.matches(&at(3, 12, 0)));
+        // a field starting with * does not count as restricted, so only fridays match
+        let cron = Cron::parse("0 12 */2 * 5").unwrap();
+        assert!(cron.matches(&at(1, 12, 0)));
+        assert!(!cron.matches(&at(3, 12, 0)));
+        assert!(cron.matches(&at(8, 12, 0)));
+    }
+}
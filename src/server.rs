@@ -4,14 +4,21 @@ use std::process::ExitCode;
 use std::sync::atomic::{AtomicBool, Ordering};
 use warp::Filter;
 use crate::api::*;
-use crate::instances::{stop_all_instances, set_java_path};
+use crate::auth::{authenticated, handle_rejection, token};
+use crate::instances::{get_stop_timeout, set_stop_timeout, stop_all_instances, wait_all_instances};
 use crate::properties::read_properties;
+use crate::schedules::run_scheduler;
 use crate::utils::filters;
 
 static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+static TLS: AtomicBool = AtomicBool::new(false);
 
 const CONFIG_FILE: &str = "mc-manager.properties";
-const DEFAULT_CONFIG_FILE: &str = "#mc-manager configurations file\r\n\r\nip=\r\nport=1234\r\njava=\r\n";
+const DEFAULT_CONFIG_FILE: &str = "#mc-manager configurations file\r\n\r\nip=\r\nport=1234\r\njava=\r\njava-runtimes=\r\njava-discover=true\r\nstop-timeout=60\r\ntls-cert=\r\ntls-key=\r\ntls-self-signed=false\r\nversion-manifest=\r\nversion-hardlink=false\r\n";
+
+/// where the self signed certificate is kept when tls-cert and tls-key are empty
+const SELF_SIGNED_CERT_FILE: &str = "mc-manager-cert.pem";
+const SELF_SIGNED_KEY_FILE: &str = "mc-manager-key.pem";
 
 pub fn is_shutdown() -> bool {
     SHUTDOWN.load(Ordering::Relaxed)
@@ -21,10 +28,24 @@ fn set_shutdown() {
     SHUTDOWN.store(true, Ordering::Relaxed);
 }
 
+/// true if the panel is served over https, so cookies must be marked as secure
+pub fn is_tls() -> bool {
+    TLS.load(Ordering::Relaxed)
+}
+
+/// in release builds, cd into folder of executable, so the config and saves are found when running as a service
+pub fn enter_executable_dir() {
+    #[cfg(not(debug_assertions))]
+    std::env::set_current_dir(std::env::current_exe().expect("current_exe").parent().expect("parent")).expect("set_current_dir");
+}
+
 pub fn serve(shutdown: Option<tokio::sync::oneshot::Receiver<()>>) -> ExitCode {
 
     let apis = filters!(
         GET async fn versions;
+        POST async fn refresh_versions;
+        GET fn installed_versions;
+        POST async fn delete_version;
         GET async fn saves;
         GET fn icons String;
         GET fn schema;
@@ -35,11 +56,105 @@ pub fn serve(shutdown: Option<tokio::sync::oneshot::Receiver<()>>) -> ExitCode {
         POST async fn delete_save;
         POST async fn start_save;
         POST async fn stop_save;
+        POST async fn kill_save;
+        POST async fn upgrade_save;
         POST async fn command;
+        GET async fn backups String;
+        POST async fn create_backup;
+        POST async fn restore_backup;
+        POST async fn delete_backup;
+        GET fn jobs;
+        GET fn job u64;
+        GET fn schedules;
+        POST async fn create_schedule;
+        POST async fn modify_schedule;
+        POST async fn delete_schedule;
+        GET fn users;
+        POST async fn create_user;
+        POST async fn modify_user;
+        POST async fn delete_user;
+        POST async fn change_password;
+        GET fn logs String;
+        GET fn runtimes;
+        GET fn mods String;
+        POST async fn toggle_mod;
+        POST async fn delete_mod;
+        GET fn datapacks String;
+        POST async fn toggle_datapack;
+        POST async fn delete_datapack;
+        POST async fn delete_file;
     );
 
-    #[cfg(not(debug_assertions))] // cd into folder of executable
-    std::env::set_current_dir(std::env::current_exe().expect("current_exe").parent().expect("parent")).expect("set_current_dir");
+    // these are the only apis that do not need a session
+    let sessions = warp::post()
+        .and(warp::path!("api" / "login"))
+        .and(warp::body::content_length_limit(1024 * 16))
+        .and(warp::body::json())
+        .and_then(login)
+        .or(warp::post().and(warp::path!("api" / "logout")).and(token()).and_then(logout))
+        .or(warp::get().and(warp::path!("api" / "session")).and(token()).and_then(session));
+    // filtered with the query string, which the filters macro does not support
+    let audit_log = warp::get()
+        .and(warp::path!("api" / "audit"))
+        .and(authenticated())
+        .and(warp::query::<crate::audit::Filter>())
+        .and_then(audit);
+    let search_log = warp::get()
+        .and(warp::path!("api" / "logs" / String / "search"))
+        .and(authenticated())
+        .and(warp::query::<crate::logs::Search>())
+        .and_then(search_logs);
+    let read_log = warp::get()
+        .and(warp::path!("api" / "logs" / String / String / String))
+        .and(authenticated())
+        .and(warp::query::<crate::logs::Range>())
+        .and_then(read_log);
+    // the jar is sent as the body, which the filters macro expects to be json
+    let upload_mod = warp::post()
+        .and(warp::path!("api" / "mods" / String / String))
+        .and(authenticated())
+        .and(warp::body::content_length_limit(crate::mods::MAX_MOD_SIZE))
+        .and(warp::body::bytes())
+        .and_then(upload_mod);
+    let upload_datapack = warp::post()
+        .and(warp::path!("api" / "datapacks" / String / String))
+        .and(authenticated())
+        .and(warp::body::content_length_limit(crate::datapacks::MAX_DATAPACK_SIZE))
+        .and(warp::body::bytes())
+        .and_then(upload_datapack);
+    // the path of the file is the rest of the url, which the filters macro can not match
+    let read_files = warp::get()
+        .and(warp::path!("api" / "files" / String / ..))
+        .and(warp::path::tail())
+        .and(authenticated())
+        .and_then(read_files);
+    let upload_file = warp::post()
+        .and(warp::path!("api" / "files" / String / ..))
+        .and(warp::path::tail())
+        .and(authenticated())
+        .and(warp::body::content_length_limit(crate::files::MAX_FILE_SIZE))
+        .and(warp::body::bytes())
+        .and_then(upload_file);
+    let import_save = warp::post()
+        .and(warp::path!("api" / "import_save" / String))
+        .and(authenticated())
+        .and(warp::query::<ImportSave>())
+        .and(warp::body::content_length_limit(crate::import::MAX_IMPORT_SIZE))
+        .and(warp::body::bytes())
+        .and_then(import_save);
+    let apis = sessions
+        .or(apis)
+        .or(audit_log)
+        .or(search_log)
+        .or(read_log)
+        .or(upload_mod)
+        .or(upload_datapack)
+        .or(read_files)
+        .or(upload_file)
+        .or(import_save)
+        .recover(handle_rejection);
+
+    enter_executable_dir();
 
     #[cfg(not(debug_assertions))] // load assets from executable
     let routes = apis.or(static_dir::static_dir!("static"));
@@ -74,6 +189,15 @@ pub fn serve(shutdown: Option<tokio::sync::oneshot::Receiver<()>>) -> ExitCode {
         }
         Err(_) => false
     };
+    let backups = match std::fs::metadata("backups") {
+        Ok(metadata) => {
+            metadata.is_dir()
+        }
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            std::fs::create_dir("backups").is_ok()
+        }
+        Err(_) => false
+    };
 
     if !config {
         println!("[!] ERROR: default \"{}\" file was not found and could not be created", CONFIG_FILE);
@@ -84,7 +208,10 @@ pub fn serve(shutdown: Option<tokio::sync::oneshot::Receiver<()>>) -> ExitCode {
     if !versions {
         println!("[!] ERROR: versions foulder was not found and could not be created");
     }
-    if !config || !saves || !versions {
+    if !backups {
+        println!("[!] ERROR: backups foulder was not found and could not be created");
+    }
+    if !config || !saves || !versions || !backups {
         return ExitCode::FAILURE;
     }
 
@@ -96,20 +223,32 @@ pub fn serve(shutdown: Option<tokio::sync::oneshot::Receiver<()>>) -> ExitCode {
         }
     };
 
+    if let Err(error) = crate::auth::load() {
+        println!("[!] ERROR: could not read users file: {error}");
+        return ExitCode::FAILURE;
+    }
+    if !crate::auth::has_users() {
+        println!("[!] WARNING: there are no users, no one will be able to log in, create one with the useradd command");
+    }
+
+    if let Err(error) = crate::schedules::load() {
+        println!("[!] ERROR: could not read schedules file: {error}");
+        return ExitCode::FAILURE;
+    }
+
     let rt = tokio::runtime::Builder::new_multi_thread()
     .enable_all()
     .build()
     .expect("failed to build runtime");
 
-    let (ip, port, java) = {
+    let (ip, port) = {
         let ip = config.get("ip");
         let port = config.get("port");
-        let java = config.get("java");
-        if let (Some(ip), Some(port), Some(java)) = (ip, port, java) {
+        if let (Some(ip), Some(port)) = (ip, port) {
             let ip = parse_ip(ip.trim());
             let port = parse_port(port.trim());
             if let (Some(ip), Some(port)) = (ip, port) {
-                (ip, port, java.to_owned())
+                (ip, port)
             } else {
                 if ip.is_none() {
                     println!("[!] ERROR: property ip is invalid");
@@ -126,42 +265,105 @@ pub fn serve(shutdown: Option<tokio::sync::oneshot::Receiver<()>>) -> ExitCode {
             if port.is_none() {
                 println!("[!] ERROR: property port was not found");
             }
-            if java.is_none() {
-                println!("[!] ERROR: property java was not found");
-            }
             return ExitCode::FAILURE;
         }
     };
 
-    set_java_path(java);
+    // java is optional since runtimes are also discovered, a save fails to start if none fits it
+    crate::java::load(&config);
 
+    // optional, empty or missing uses the manifest of mojang
+    crate::versions::set_manifest_url(config.get("version-manifest").map_or("", |x| x.trim()));
+    crate::versions::set_hardlink(config.get("version-hardlink").is_some_and(|x| x.trim() == "true"));
+
+    // optional, config files created by older versions do not have it
+    if let Some(stop_timeout) = config.get("stop-timeout") {
+        match stop_timeout.trim().parse() {
+            Ok(stop_timeout) => set_stop_timeout(stop_timeout),
+            Err(_) => {
+                println!("[!] ERROR: property stop-timeout is invalid");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    // optional, without a certificate the panel is served over plain http
+    let tls = match read_tls_config(&config, ip) {
+        Ok(tls) => tls,
+        Err(error) => {
+            println!("[!] ERROR: {error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    TLS.store(tls.is_some(), Ordering::Relaxed);
+
+    let scheme = if tls.is_some() { "https" } else { "http" };
     if ip == [0, 0, 0, 0] {
-        println!("[*] Minecraft Server Manager *:{}", port);
+        println!("[*] Minecraft Server Manager {}://*:{}", scheme, port);
     } else {
-        println!("[*] Minecraft Server Manager {}.{}.{}.{}:{}", ip[0], ip[1], ip[2], ip[3], port);
+        println!("[*] Minecraft Server Manager {}://{}.{}.{}.{}:{}", scheme, ip[0], ip[1], ip[2], ip[3], port);
     }
 
     let _enter = rt.enter();
-    rt.block_on(
-        warp::serve(routes).bind_with_graceful_shutdown((ip, port), async move {
-            if let Some(shutdown) = shutdown {
-                shutdown.await.expect("The shutdown oneshot chanel's sender was dropped");
-                println!("[*] Stopping service");
-            } else {
-                if let Err(_) = tokio::signal::ctrl_c().await {
-                    println!("[!] Failed to detect CTRL-C");
-                    // the line below never returns
-                    let () = std::future::pending().await;
-                }
-                println!("[*] CTRL-C detected");
-            }
-            set_shutdown();
-            stop_all_instances().await;
-        }).1
-    );
+    rt.spawn(run_scheduler());
+    let shutdown = async move {
+        if let Some(shutdown) = shutdown {
+            shutdown.await.expect("The shutdown oneshot chanel's sender was dropped");
+            println!("[*] Stopping service");
+        } else {
+            wait_for_signal().await;
+        }
+        set_shutdown();
+        stop_all_instances().await;
+    };
+    match tls {
+        Some(tls) => rt.block_on(
+            warp::serve(routes).tls().cert(tls.cert).key(tls.key).bind_with_graceful_shutdown((ip, port), shutdown).1
+        ),
+        None => rt.block_on(
+            warp::serve(routes).bind_with_graceful_shutdown((ip, port), shutdown).1
+        ),
+    }
+    if !rt.block_on(wait_all_instances(get_stop_timeout())) {
+        println!("[!] Some instances may still be running");
+        return ExitCode::FAILURE;
+    }
+    println!("[*] All instances stopped");
     ExitCode::SUCCESS
 }
 
+/// waits for CTRL-C, or on unix also for SIGTERM, which is what systemd sends to stop the service
+async fn wait_for_signal() {
+    let ctrl_c = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            println!("[!] Failed to detect CTRL-C");
+            // the line below never returns
+            let () = std::future::pending().await;
+        }
+        println!("[*] CTRL-C detected");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+                println!("[*] SIGTERM detected");
+            }
+            Err(_) => {
+                println!("[!] Failed to detect SIGTERM");
+                let () = std::future::pending().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+}
+
 fn parse_ip(ip: &str) -> Option<[u8; 4]> {
     if ip.is_empty() {
         return Some([0, 0, 0, 0]);
@@ -181,3 +383,60 @@ fn parse_ip(ip: &str) -> Option<[u8; 4]> {
 fn parse_port(port: &str) -> Option<u16> {
     port.parse().ok()
 }
+
+/// the certificate chain and private key, in the pem format
+struct TlsConfig {
+    cert: Vec<u8>,
+    key: Vec<u8>,
+}
+
+/// reads the certificate and private key set by tls-cert and tls-key
+///
+/// if tls-self-signed is true and the files do not exist yet, a self signed certificate is generated,
+/// browsers will warn about it, but the traffic is still encrypted
+fn read_tls_config(
+    config: &std::collections::HashMap<String, String>,
+    ip: [u8; 4],
+) -> Result<Option<TlsConfig>, String> {
+    let property = |name: &str| config.get(name).map(|x| x.trim()).filter(|x| !x.is_empty());
+    let self_signed = match property("tls-self-signed") {
+        None | Some("false") => false,
+        Some("true") => true,
+        Some(_) => return Err("property tls-self-signed is invalid".to_owned()),
+    };
+    let (cert_file, key_file) = match (property("tls-cert"), property("tls-key")) {
+        (Some(cert), Some(key)) => (cert, key),
+        (None, None) if self_signed => (SELF_SIGNED_CERT_FILE, SELF_SIGNED_KEY_FILE),
+        (None, None) => return Ok(None),
+        (Some(_), None) => return Err("property tls-key is required when tls-cert is set".to_owned()),
+        (None, Some(_)) => return Err("property tls-cert is required when tls-key is set".to_owned()),
+    };
+    let missing = |file: &str| matches!(std::fs::metadata(file), Err(error) if error.kind() == ErrorKind::NotFound);
+    if self_signed && missing(cert_file) && missing(key_file) {
+        generate_self_signed(cert_file, key_file, ip)?;
+        println!("[*] Generated a self signed certificate in \"{cert_file}\"");
+    }
+    let cert = std::fs::read(cert_file).map_err(|error| format!("could not read tls certificate \"{cert_file}\": {error}"))?;
+    let key = std::fs::read(key_file).map_err(|error| format!("could not read tls key \"{key_file}\": {error}"))?;
+    Ok(Some(TlsConfig { cert, key }))
+}
+
+fn generate_self_signed(cert_file: &str, key_file: &str, ip: [u8; 4]) -> Result<(), String> {
+    let mut names = vec!["localhost".to_owned(), "127.0.0.1".to_owned()];
+    if ip != [0, 0, 0, 0] && ip != [127, 0, 0, 1] {
+        names.push(std::net::Ipv4Addr::from(ip).to_string());
+    }
+    let certified = rcgen::generate_simple_self_signed(names)
+        .map_err(|error| format!("could not generate a self signed certificate: {error}"))?;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    // only the user running the manager may read the private key
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(key_file)
+        .and_then(|mut file| std::io::Write::write_all(&mut file, certified.key_pair.serialize_pem().as_bytes()))
+        .map_err(|error| format!("could not write tls key \"{key_file}\": {error}"))?;
+    std::fs::write(cert_file, certified.cert.pem())
+        .map_err(|error| format!("could not write tls certificate \"{cert_file}\": {error}"))
+}
@@ -1,67 +1,12 @@
 use crate::instances::InstanceStatus;
 use crate::properties::*;
 use crate::utils::{append_comma_separated, append_json_string, now, ApiError};
-use std::collections::HashMap;
-use std::io::Write;
+use lazy_static::lazy_static;
+use std::collections::{HashMap, HashSet};
 
-pub async fn download_version(version: &str) -> Result<(), ApiError> {
-    let path = format!("versions/{version}.jar");
-    if std::fs::metadata(&path).is_ok_and(|x| x.is_file()) {
-        return Ok(());
-    }
-    let client = reqwest::Client::new();
-    let html = match async {
-        client
-            .get(format!("https://mcversions.net/download/{version}.html"))
-            .send()
-            .await?
-            .text()
-            .await
-    }
-    .await
-    {
-        Ok(html) => html,
-        Err(error) => return Err(ApiError::IOError(error.to_string())),
-    };
-    let Some(link) = html
-        .split("<a ")
-        .skip(1)
-        .filter_map(|version| {
-            version
-                .split_once(">Download Server Jar</a>")
-                .and_then(|(attrs, _)| attrs.split_once("href=\""))
-                .and_then(|(_, href)| href.split_once('"'))
-                .map(|(link, _)| link)
-        })
-        .next()
-    else {
-        return Ok(());
-    };
-
-    let mut file = std::fs::File::options()
-        .write(true)
-        .create(true)
-        .open(&path)?;
-
-    async {
-        let mut response = client
-            .get(link)
-            .send()
-            .await
-            .map_err(|x| ApiError::IOError(x.to_string()))?;
-        while let Some(chunk) = response
-            .chunk()
-            .await
-            .map_err(|x| ApiError::IOError(x.to_string()))?
-        {
-            file.write_all(&chunk)?;
-        }
-        Ok(())
-    }
-    .await
-    .inspect_err(|_| {
-        let _ = std::fs::remove_file(&path);
-    })
+lazy_static! {
+    /// saves that are being worked on by a long operation, like a backup or a restore
+    static ref BUSY: std::sync::Mutex<HashSet<String>> = std::sync::Mutex::new(HashSet::new());
 }
 
 /// an iterator to list all saves in the saves folder
@@ -69,6 +14,9 @@ pub async fn download_version(version: &str) -> Result<(), ApiError> {
 /// instanciate with `Save::iter()`
 pub struct SaveIter(Option<std::fs::ReadDir>);
 
+/// marks a save as busy while it lives, acquire with `save::lock`
+pub struct SaveLock(String);
+
 pub mod save {
     use std::io::ErrorKind;
 
@@ -84,7 +32,7 @@ pub mod save {
             Err(error) => return Err(error),
             Ok(()) => return Err(ApiError::AlreadyExists),
         }
-        if !std::fs::metadata(format!("versions/{version}.jar")).is_ok() {
+        if std::fs::metadata(format!("versions/{version}.jar")).is_err() {
             return Err(ApiError::VersionNotFound);
         }
         validate_properties(&values)?;
@@ -97,10 +45,7 @@ pub mod save {
                 "# file created by mc-manager\r\neula=true\r\n",
             )?;
             std::fs::write(format!("saves/{name}/server.properties"), properties)?;
-            std::fs::copy(
-                format!("versions/{version}.jar"),
-                format!("saves/{name}/server.jar"),
-            )?;
+            crate::versions::install(version, &format!("saves/{name}/server.jar"))?;
             Ok::<(), std::io::Error>(())
         })() {
             std::fs::remove_dir_all(format!("saves/{name}"))?;
@@ -111,8 +56,9 @@ pub mod save {
     }
     /// delete the save specified and all backups
     pub fn delete(name: &str) -> Result<(), ApiError> {
+        let _lock = lock(name)?;
         std::fs::remove_dir_all(format!("saves/{name}"))?;
-        Ok(())
+        super::backup::delete_all(name)
     }
     /// returns a valid json with all of the properties for a save, including its name, and its status
     ///
@@ -130,6 +76,7 @@ pub mod save {
             InstanceStatus::Online => out += "\"online\"",
             InstanceStatus::Shutdown => out += "\"shutdown\"",
             InstanceStatus::Offline => out += "\"offline\"",
+            InstanceStatus::Crashed => out += "\"crashed\"",
         }
         for prop in PROPERTIES.iter() {
             if prop.access == PropAccess::None {
@@ -147,7 +94,7 @@ pub mod save {
                         out += value;
                     }
                     PropType::String(_) | PropType::Datetime | PropType::StrEnum(..) => {
-                        append_json_string(&mut out, &value)
+                        append_json_string(&mut out, value)
                     }
                 }
             } else {
@@ -163,6 +110,28 @@ pub mod save {
         validate_properties(&values)?;
         write_properties(format!("saves/{name}/server.properties"), values)
     }
+    /// replaces server.jar with the jar of an installed version and records the version
+    ///
+    /// the caller must hold the lock of the save and make sure the server is offline,
+    /// a jar set in `mc-manager-server-jar` is not changed
+    pub fn set_version(name: &str, version: &str) -> Result<(), ApiError> {
+        exists(name)?;
+        let jar = format!("saves/{name}/server.jar");
+        let temp = format!("saves/{name}/server.jar.new");
+        let _ = std::fs::remove_file(&temp);
+        // renamed over the old one, so a jar hardlinked to the versions folder is never written to
+        crate::versions::install(version, &temp)?;
+        if let Err(error) = std::fs::rename(&temp, &jar) {
+            let _ = std::fs::remove_file(&temp);
+            return Err(error.into());
+        }
+        let mut values = HashMap::new();
+        values.insert(
+            "mc-manager-server-version".to_owned(),
+            PropValue::String(version.to_owned()),
+        );
+        write_properties(format!("saves/{name}/server.properties"), values)
+    }
     /// update the access time of the world specified to now
     pub fn access(name: &str) -> Result<(), ApiError> {
         let mut values = HashMap::new();
@@ -219,7 +188,7 @@ pub mod save {
                     *out += r#"{"name":"integer-enum","default":"#;
                     *out += &value.to_string();
                     *out += r#","members":["#;
-                    append_comma_separated(members.iter().map(|x| *x), out, append_json_string);
+                    append_comma_separated(members.iter().copied(), out, append_json_string);
                     *out += "]}";
                 }
                 PropType::StrEnum(value, members) => {
@@ -244,7 +213,7 @@ pub mod save {
         });
         out += r#"},"create_properties":["#;
         append_comma_separated(
-            CREATE_PROPERTIES.iter().map(|x| *x),
+            CREATE_PROPERTIES.iter().copied(),
             &mut out,
             append_json_string,
         );
@@ -258,6 +227,23 @@ pub mod save {
             Err(error) => Err(error.into()),
         }
     }
+    /// marks the save as busy until the returned lock is dropped, returns SaveBusy if it already is
+    pub fn lock(name: &str) -> Result<SaveLock, ApiError> {
+        let mut busy = BUSY.lock().expect("BUSY lock is poisoned");
+        if busy.insert(name.to_owned()) {
+            Ok(SaveLock(name.to_owned()))
+        } else {
+            Err(ApiError::SaveBusy)
+        }
+    }
+    /// returns SaveBusy if a long operation is being done on the save
+    pub fn check_busy(name: &str) -> Result<(), ApiError> {
+        if BUSY.lock().expect("BUSY lock is poisoned").contains(name) {
+            Err(ApiError::SaveBusy)
+        } else {
+            Ok(())
+        }
+    }
     /// returns the apropiate error if the save does not exist, may return IOError
     pub fn exists(name: &str) -> Result<(), ApiError> {
         match std::fs::metadata(format!("saves/{name}")) {
@@ -279,6 +265,237 @@ pub mod save {
     }
 }
 
+pub mod backup {
+    use super::*;
+    use crate::logs::LOGS_DIR;
+    use std::fs::File;
+    use std::io::ErrorKind;
+    use std::path::Path;
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+    /// the format of backup ids, which are also their file names, without the extension
+    const ID_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+    /// appended to the ids of backups made by a schedule, only those are removed by `prune`
+    const AUTOMATIC_SUFFIX: &str = "_auto";
+
+    /// archives the whole save folder into a new backup, returns the id of the backup
+    ///
+    /// the caller must hold the lock of the save and make sure the server is not writing to it,
+    /// `progress` is called with the number of files archived so far and the total
+    pub fn create(
+        name: &str,
+        automatic: bool,
+        mut progress: impl FnMut(u64, u64),
+    ) -> Result<String, ApiError> {
+        save::exists(name)?;
+        std::fs::create_dir_all(format!("backups/{name}"))?;
+        let mut id = chrono::Local::now().format(ID_FORMAT).to_string();
+        if automatic {
+            id += AUTOMATIC_SUFFIX;
+        }
+        if std::fs::metadata(format!("backups/{name}/{id}.zip")).is_ok() {
+            // two backups in the same second
+            let mut index = 2;
+            while std::fs::metadata(format!("backups/{name}/{id}_{index}.zip")).is_ok() {
+                index += 1;
+            }
+            id = format!("{id}_{index}");
+        }
+        let temp = format!("backups/{name}/{id}.zip.tmp");
+        if let Err(error) = (|| {
+            let dir = format!("saves/{name}");
+            let total = count_files(Path::new(&dir))?;
+            let mut done = 0;
+            progress(done, total);
+            let mut writer = ZipWriter::new(File::create(&temp)?);
+            append_dir(&mut writer, Path::new(&dir), "", &mut || {
+                done += 1;
+                progress(done, total);
+            })?;
+            writer.finish()?;
+            std::fs::rename(&temp, format!("backups/{name}/{id}.zip"))?;
+            Ok::<(), ApiError>(())
+        })() {
+            let _ = std::fs::remove_file(&temp);
+            return Err(error);
+        }
+        Ok(id)
+    }
+    /// returns a valid json with all of the backups of a save, newest first
+    pub fn list(name: &str) -> Result<String, ApiError> {
+        let backups = read(name)?;
+        let mut out = String::with_capacity(4 * 1024);
+        out += r#"{"backups":["#;
+        append_comma_separated(backups.iter(), &mut out, |out, (id, size)| {
+            *out += r#"{"id":"#;
+            append_json_string(out, id);
+            *out += r#","time":"#;
+            match chrono::NaiveDateTime::parse_from_str(&id[..id.len().min(19)], ID_FORMAT) {
+                Ok(time) => append_json_string(out, &time.format("%Y-%m-%d %H:%M:%S").to_string()),
+                Err(_) => *out += "null",
+            }
+            *out += r#","size":"#;
+            *out += &size.to_string();
+            *out += r#","automatic":"#;
+            *out += if id.contains(AUTOMATIC_SUFFIX) { "true" } else { "false" };
+            *out += "}";
+        });
+        out += "]}";
+        Ok(out)
+    }
+    /// deletes the oldest automatic backups of the save, until only `keep` of them remain
+    pub fn prune(name: &str, keep: usize) -> Result<(), ApiError> {
+        let backups = read(name)?;
+        for (id, _) in backups
+            .iter()
+            .filter(|(id, _)| id.contains(AUTOMATIC_SUFFIX))
+            .skip(keep)
+        {
+            delete(name, id)?;
+        }
+        Ok(())
+    }
+    /// returns the ids and sizes of all backups of the save, newest first
+    fn read(name: &str) -> Result<Vec<(String, u64)>, ApiError> {
+        save::exists(name)?;
+        let mut backups = Vec::new();
+        let paths = match std::fs::read_dir(format!("backups/{name}")) {
+            Ok(paths) => Some(paths),
+            Err(error) if error.kind() == ErrorKind::NotFound => None,
+            Err(error) => return Err(error.into()),
+        };
+        for path in paths.into_iter().flatten() {
+            let path = path?;
+            let Some(filename) = path.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+            let Some(id) = filename.strip_suffix(".zip") else {
+                continue;
+            };
+            let metadata = path.metadata()?;
+            if metadata.is_file() {
+                backups.push((id.to_owned(), metadata.len()));
+            }
+        }
+        backups.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+        Ok(backups)
+    }
+    /// replaces the save folder with the contents of the backup
+    ///
+    /// the caller must hold the lock of the save and make sure the server is not running
+    pub fn restore(name: &str, id: &str) -> Result<(), ApiError> {
+        save::exists(name)?;
+        let archive = format!("backups/{name}/{id}.zip");
+        let file = match File::open(&archive) {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                return Err(ApiError::BackupNotFound)
+            }
+            Err(error) => return Err(error.into()),
+        };
+        let temp = format!("backups/{name}/.restore");
+        let replaced = format!("backups/{name}/.replaced");
+        for dir in [&temp, &replaced] {
+            if std::fs::metadata(dir).is_ok() {
+                std::fs::remove_dir_all(dir)?;
+            }
+        }
+        if let Err(error) = ZipArchive::new(file).and_then(|mut zip| zip.extract(&temp)) {
+            let _ = std::fs::remove_dir_all(&temp);
+            return Err(error.into());
+        }
+        std::fs::rename(format!("saves/{name}"), &replaced)?;
+        if let Err(error) = std::fs::rename(&temp, format!("saves/{name}")) {
+            // put the original back
+            std::fs::rename(&replaced, format!("saves/{name}"))?;
+            let _ = std::fs::remove_dir_all(&temp);
+            return Err(error.into());
+        }
+        // backups do not have the console logs, keep the current ones
+        let logs = format!("{replaced}/{LOGS_DIR}");
+        if std::fs::metadata(&logs).is_ok() {
+            std::fs::rename(&logs, format!("saves/{name}/{LOGS_DIR}"))?;
+        }
+        std::fs::remove_dir_all(&replaced)?;
+        Ok(())
+    }
+    /// deletes one backup of the save
+    pub fn delete(name: &str, id: &str) -> Result<(), ApiError> {
+        save::exists(name)?;
+        match std::fs::remove_file(format!("backups/{name}/{id}.zip")) {
+            Ok(()) => Ok(()),
+            Err(error) if error.kind() == ErrorKind::NotFound => Err(ApiError::BackupNotFound),
+            Err(error) => Err(error.into()),
+        }
+    }
+    /// deletes all backups of the save, does not check if the save exists
+    pub fn delete_all(name: &str) -> Result<(), ApiError> {
+        match std::fs::remove_dir_all(format!("backups/{name}")) {
+            Ok(()) => Ok(()),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(error.into()),
+        }
+    }
+    /// counts the files inside `dir` recursively
+    fn count_files(dir: &Path) -> Result<u64, ApiError> {
+        let mut count = 0;
+        for path in std::fs::read_dir(dir)? {
+            let path = path?;
+            let metadata = path.metadata()?;
+            if metadata.is_dir() && path.file_name() == LOGS_DIR {
+                continue;
+            }
+            if metadata.is_dir() {
+                count += count_files(&path.path())?;
+            } else if metadata.is_file() {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+    /// adds all files in `dir` to the archive recursively, `prefix` is the path inside the archive
+    ///
+    /// `added` is called after each file is added
+    fn append_dir(
+        writer: &mut ZipWriter<File>,
+        dir: &Path,
+        prefix: &str,
+        added: &mut dyn FnMut(),
+    ) -> Result<(), ApiError> {
+        for path in std::fs::read_dir(dir)? {
+            let path = path?;
+            let Some(filename) = path.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+            let metadata = path.metadata()?;
+            let entry = format!("{prefix}{filename}");
+            // the console logs are not part of the world, and restoring them would lose the newer ones
+            if metadata.is_dir() && filename == LOGS_DIR {
+                continue;
+            }
+            if metadata.is_dir() {
+                writer.add_directory(entry.as_str(), SimpleFileOptions::default())?;
+                append_dir(writer, &path.path(), &format!("{entry}/"), added)?;
+            } else if metadata.is_file() {
+                let options = SimpleFileOptions::default()
+                    .compression_method(CompressionMethod::Deflated)
+                    .large_file(metadata.len() >= u32::MAX as u64);
+                writer.start_file(entry.as_str(), options)?;
+                std::io::copy(&mut File::open(path.path())?, writer)?;
+                added();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SaveLock {
+    fn drop(&mut self) {
+        BUSY.lock().expect("BUSY lock is poisoned").remove(&self.0);
+    }
+}
+
 impl Iterator for SaveIter {
     type Item = Result<String, ApiError>;
 
@@ -0,0 +1,163 @@
+use crate::{DESCRIPTION, DISPLAY_NAME};
+use std::io::ErrorKind;
+use std::process::Command;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const SERVICE_NAME: &str = "mc-manager";
+const UNIT_PATH: &str = "/etc/systemd/system/mc-manager.service";
+
+pub fn install_service() {
+    match std::fs::metadata(UNIT_PATH) {
+        Ok(_) => {
+            println!("O serviço já está instalado");
+            return;
+        }
+        Err(error) if error.kind() == ErrorKind::NotFound => {}
+        Err(error) => panic!("metadata: {error}"),
+    }
+
+    let service_binary_path = std::env::current_exe().expect("current_exe");
+    let working_directory = service_binary_path.parent().expect("parent");
+
+    let mut unit = String::with_capacity(1024);
+    unit += "[Unit]\n";
+    unit += "Description=";
+    unit += DISPLAY_NAME;
+    unit += " - ";
+    unit += DESCRIPTION;
+    unit += "\nAfter=network-online.target\nWants=network-online.target\n\n";
+    unit += "[Service]\nType=simple\n";
+    unit += "ExecStart=\"";
+    unit += &service_binary_path.to_string_lossy();
+    unit += "\" run\n";
+    unit += "WorkingDirectory=";
+    unit += &working_directory.to_string_lossy();
+    unit += "\n";
+    // when installed through sudo, run as the user that invoked it instead of root
+    if let Ok(user) = std::env::var("SUDO_USER") {
+        if !user.is_empty() && user != "root" {
+            unit += "User=";
+            unit += &user;
+            unit += "\n";
+        }
+    }
+    unit += "KillMode=mixed\nTimeoutStopSec=90\nRestart=on-failure\n\n";
+    unit += "[Install]\nWantedBy=multi-user.target\n";
+
+    std::fs::write(UNIT_PATH, unit).expect("write unit file");
+    systemctl(&["daemon-reload"]);
+    systemctl(&["enable", SERVICE_NAME]);
+    println!("O serviço foi instalado");
+}
+
+pub fn uninstall_service() {
+    match std::fs::metadata(UNIT_PATH) {
+        Ok(_) => {}
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            println!("O serviço não está instalado");
+            return;
+        }
+        Err(error) => panic!("metadata: {error}"),
+    }
+    systemctl(&["disable", "--now", SERVICE_NAME]);
+    std::fs::remove_file(UNIT_PATH).expect("remove unit file");
+    systemctl(&["daemon-reload"]);
+    println!("O serviço foi desinstalado");
+}
+
+pub fn start_service() {
+    if !is_installed() {
+        return;
+    }
+    systemctl(&["start", "--no-block", SERVICE_NAME]);
+    println!("O serviço está iniciando");
+}
+
+pub fn stop_service() {
+    if !is_installed() {
+        return;
+    }
+    systemctl(&["stop", "--no-block", SERVICE_NAME]);
+
+    let start = Instant::now();
+    let timeout = Duration::from_secs(5);
+    while start.elapsed() < timeout {
+        match active_state().as_str() {
+            "inactive" | "failed" => {
+                println!("O serviço está parado");
+                return;
+            }
+            "deactivating" | "active" => sleep(Duration::from_secs(1)),
+            "activating" => {
+                println!("Ocorreu um erro ao parar o serviço\r\nO serviço está iniciando");
+                return;
+            }
+            _ => break,
+        }
+    }
+    println!("O serviço está parando");
+}
+
+pub fn status_service() {
+    if !is_installed() {
+        return;
+    }
+    match active_state().as_str() {
+        "inactive" => println!("O serviço está parado"),
+        "failed" => println!("O serviço está parado, após uma falha"),
+        "activating" => println!("O serviço está iniciando"),
+        "deactivating" => println!("O serviço está parando"),
+        "active" => println!("O serviço está executando"),
+        "reloading" => println!("O serviço está recarregando"),
+        state => println!("O serviço está em um estado desconhecido: {state}"),
+    }
+}
+
+pub fn is_admin() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// turns the echo of the terminal on or off, returns false if stdin is not a terminal
+pub fn set_echo(enabled: bool) -> bool {
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+            return false;
+        }
+        if enabled {
+            termios.c_lflag |= libc::ECHO;
+        } else {
+            termios.c_lflag &= !libc::ECHO;
+        }
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) == 0
+    }
+}
+
+fn is_installed() -> bool {
+    if std::fs::metadata(UNIT_PATH).is_ok() {
+        true
+    } else {
+        println!("O serviço não esta instalado");
+        false
+    }
+}
+
+/// returns the ActiveState of the unit, as reported by `systemctl is-active`
+fn active_state() -> String {
+    let output = Command::new("systemctl")
+        .args(["is-active", SERVICE_NAME])
+        .output()
+        .expect("systemctl");
+    String::from_utf8_lossy(&output.stdout).trim().to_owned()
+}
+
+fn systemctl(args: &[&str]) {
+    let status = Command::new("systemctl")
+        .args(args)
+        .status()
+        .expect("systemctl");
+    if !status.success() {
+        println!("[!] systemctl {} failed with {status}", args.join(" "));
+    }
+}
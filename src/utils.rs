@@ -8,6 +8,7 @@ macro_rules! filters {
             )))
             $(.and(warp::path::param::<$ty>()))*
             .and(warp::path::end())
+            .and(crate::auth::authenticated())
             .and_then($func_name)
     }};
     (GET fn $func_name:ident $($ty:ty)*;) => {{
@@ -19,6 +20,7 @@ macro_rules! filters {
             )))
             $(.and(warp::path::param::<$ty>()))*
             .and(warp::path::end())
+            .and(crate::auth::authenticated())
             .map($func_name)
     }};
     (POST async fn $func_name:ident $($ty:ty)*;) => {{
@@ -30,6 +32,7 @@ macro_rules! filters {
             )))
             $(.and(warp::path::param::<$ty>()))*
             .and(warp::path::end())
+            .and(crate::auth::authenticated())
             .and(warp::body::content_length_limit(1024 * 16))
             .and(warp::body::json())
             .and_then($func_name)
@@ -43,6 +46,7 @@ macro_rules! filters {
             )))
             $(.and(warp::path::param::<$ty>()))*
             .and(warp::path::end())
+            .and(crate::auth::authenticated())
             .and(warp::body::content_length_limit(1024 * 16))
             .and(warp::body::json())
             .map($func_name)
@@ -55,6 +59,7 @@ macro_rules! filters {
             )))
             $(.and(warp::path::param::<$ty>()))*
             .and(warp::path::end())
+            .and(crate::auth::authenticated())
             .and(warp::ws())
             .and_then($func_name)
     }};
@@ -66,6 +71,7 @@ macro_rules! filters {
             )))
             $(.and(warp::path::param::<$ty>()))*
             .and(warp::path::end())
+            .and(crate::auth::authenticated())
             .and(warp::ws())
             .map($func_name)
     }};
@@ -81,15 +87,52 @@ pub(crate) use filters;
 
 use warp::{reply::Response, Reply, hyper::StatusCode};
 
-use crate::instances::{InstanceStatus, get_java_path};
+use crate::instances::InstanceStatus;
 
 #[derive(Clone, PartialEq, Eq)]
 pub enum ApiError {
     BadRequest,
+    Unauthorized,
+    BadCredentials,
+    Forbidden,
+    UserAlreadyExists,
+    UserNotFound,
+    WeakPassword,
+    LastAdmin,
     BadName,
     NotFound,
     AlreadyExists,
+    SaveBusy,
+    BackupNotFound,
+    JobNotFound,
+    LogNotFound,
+    SearchInvalid(String),
+    ScheduleNotFound,
+    ScheduleInvalid(String),
     VersionNotFound,
+    /// a save was created with the version, so it can not be deleted
+    VersionInUse,
+    /// the version is older than the one of the save, or it can not be told, and force was not set
+    VersionDowngrade,
+    /// the flavor has no build for the minecraft version, or the requested build does not exist
+    LoaderNotFound,
+    /// the operation does not work with the flavor of the save, like upgrading a modded save or adding mods to vanilla
+    FlavorNotSupported,
+    ModNotFound,
+    /// the uploaded file is not a jar
+    ModInvalid,
+    DatapackNotFound,
+    /// the uploaded file is not a zip with a pack.mcmeta
+    DatapackInvalid,
+    FileNotFound,
+    /// the uploaded file is not a zip with a world or a server folder
+    ImportInvalid,
+    /// the version could not be read from the level.dat of the imported world, and was not given
+    ImportVersionUnknown,
+    /// the version server or the download of the jar could not be reached
+    DownloadFailed(String),
+    /// the downloaded jar does not match the published sha1 or size
+    ChecksumMismatch,
     PropertyNotFound(String),
     PropertyReadOnly(String),
     PropertyInvalid(String),
@@ -97,6 +140,8 @@ pub enum ApiError {
     BadInstanceStatus(InstanceStatus),
     PortInUse,
     JavaError(String),
+    /// no java runtime found can run the save, holds the java versions needed, like 17+ or 8-16
+    JavaNotFound(String),
     IOError(String),
 }
 
@@ -121,40 +166,142 @@ impl Reply for ApiError
 {
     fn into_response(self) -> Response {
         let status = match self {
+            Self::BadRequest => return StatusCode::BAD_REQUEST.into_response(),
+            Self::Unauthorized | Self::BadCredentials => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
             Self::IOError(_) | Self::JavaError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::DownloadFailed(_) | Self::ChecksumMismatch => StatusCode::BAD_GATEWAY,
             _ => StatusCode::BAD_REQUEST,
         };
-        let body = match self {
-            Self::BadRequest => return StatusCode::BAD_REQUEST.into_response(),
+        json_response_with_status(self.to_json(), status)
+    }
+}
+
+impl ApiError {
+    /// the name of the error, the same that is sent in the `err` field
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::BadRequest => "BadRequest",
+            Self::Unauthorized => "Unauthorized",
+            Self::BadCredentials => "BadCredentials",
+            Self::Forbidden => "Forbidden",
+            Self::UserAlreadyExists => "UserAlreadyExists",
+            Self::UserNotFound => "UserNotFound",
+            Self::WeakPassword => "WeakPassword",
+            Self::LastAdmin => "LastAdmin",
+            Self::BadName => "BadName",
+            Self::NotFound => "NotFound",
+            Self::AlreadyExists => "AlreadyExists",
+            Self::SaveBusy => "SaveBusy",
+            Self::BackupNotFound => "BackupNotFound",
+            Self::JobNotFound => "JobNotFound",
+            Self::LogNotFound => "LogNotFound",
+            Self::SearchInvalid(_) => "SearchInvalid",
+            Self::ScheduleNotFound => "ScheduleNotFound",
+            Self::ScheduleInvalid(_) => "ScheduleInvalid",
+            Self::VersionNotFound => "VersionNotFound",
+            Self::VersionInUse => "VersionInUse",
+            Self::VersionDowngrade => "VersionDowngrade",
+            Self::LoaderNotFound => "LoaderNotFound",
+            Self::FlavorNotSupported => "FlavorNotSupported",
+            Self::ModNotFound => "ModNotFound",
+            Self::ModInvalid => "ModInvalid",
+            Self::DatapackNotFound => "DatapackNotFound",
+            Self::DatapackInvalid => "DatapackInvalid",
+            Self::FileNotFound => "FileNotFound",
+            Self::ImportInvalid => "ImportInvalid",
+            Self::ImportVersionUnknown => "ImportVersionUnknown",
+            Self::DownloadFailed(_) => "DownloadFailed",
+            Self::ChecksumMismatch => "ChecksumMismatch",
+            Self::PropertyNotFound(_) => "PropertyNotFound",
+            Self::PropertyReadOnly(_) => "PropertyReadOnly",
+            Self::PropertyInvalid(_) => "PropertyInvalid",
+            Self::BadConfig(_) => "BadConfig",
+            Self::BadInstanceStatus(_) => "BadInstanceStatus",
+            Self::PortInUse => "PortInUse",
+            Self::JavaError(_) => "JavaError",
+            Self::JavaNotFound(_) => "JavaNotFound",
+            Self::IOError(_) => "IOError",
+        }
+    }
+    /// the json that describes this error, the same that is sent as the body of the error response
+    pub fn to_json(&self) -> String {
+        match self {
+            Self::BadRequest => r#"{"err":"BadRequest"}"#.to_owned(),
+            Self::Unauthorized => r#"{"err":"Unauthorized","desc":"É necessário entrar com um usuário e senha"}"#.to_owned(),
+            Self::BadCredentials => r#"{"err":"BadCredentials","desc":"Usuário ou senha incorretos"}"#.to_owned(),
+            Self::Forbidden => r#"{"err":"Forbidden","desc":"Você não tem permissão para fazer isso"}"#.to_owned(),
+            Self::UserAlreadyExists => r#"{"err":"UserAlreadyExists","desc":"O nome já é usado por outro usuário"}"#.to_owned(),
+            Self::UserNotFound => r#"{"err":"UserNotFound","desc":"O usuário não foi encontrado"}"#.to_owned(),
+            Self::WeakPassword => r#"{"err":"WeakPassword","desc":"A senha precisa ter pelo menos 8 caracteres"}"#.to_owned(),
+            Self::LastAdmin => r#"{"err":"LastAdmin","desc":"O último administrador não pode ser removido ou rebaixado"}"#.to_owned(),
             Self::BadName => r#"{"err":"BadName","desc":"Esse nome não pode ser usado como nome de um mundo"}"#.to_owned(),
             Self::NotFound => r#"{"err":"NotFound","desc":"O save não foi encontrado"}"#.to_owned(),
             Self::AlreadyExists => r#"{"err":"AlreadyExists","desc":"O nome já é usado por um save"}"#.to_owned(),
+            Self::SaveBusy => r#"{"err":"SaveBusy","desc":"O save está ocupado com outra operação, tente novamente mais tarde"}"#.to_owned(),
+            Self::BackupNotFound => r#"{"err":"BackupNotFound","desc":"O backup não foi encontrado"}"#.to_owned(),
+            Self::JobNotFound => r#"{"err":"JobNotFound","desc":"A tarefa não foi encontrada"}"#.to_owned(),
+            Self::LogNotFound => r#"{"err":"LogNotFound","desc":"O arquivo de log não foi encontrado"}"#.to_owned(),
+            Self::SearchInvalid(error) => {
+                let mut out = String::with_capacity(256);
+                out.push_str(r#"{"err":"SearchInvalid","desc":"A busca é inválida","error":"#);
+                append_json_string(&mut out, error);
+                out.push('}');
+                out
+            },
+            Self::ScheduleNotFound => r#"{"err":"ScheduleNotFound","desc":"O agendamento não foi encontrado"}"#.to_owned(),
+            Self::ScheduleInvalid(field) => {
+                let mut out = String::with_capacity(256);
+                out.push_str(r#"{"err":"ScheduleInvalid","desc":"O agendamento é inválido","field":"#);
+                append_json_string(&mut out, field);
+                out.push('}');
+                out
+            },
             Self::VersionNotFound => r#"{"err":"VersionNotFound","desc":"A versão não existe, ou não está instalada"}"#.to_owned(),
+            Self::VersionInUse => r#"{"err":"VersionInUse","desc":"A versão está sendo usada por um save"}"#.to_owned(),
+            Self::VersionDowngrade => r#"{"err":"VersionDowngrade","desc":"Voltar para uma versão anterior pode corromper o mundo, confirme para continuar"}"#.to_owned(),
+            Self::LoaderNotFound => r#"{"err":"LoaderNotFound","desc":"Não existe uma build desse servidor para a versão"}"#.to_owned(),
+            Self::FlavorNotSupported => r#"{"err":"FlavorNotSupported","desc":"Essa operação não é suportada pelo tipo de servidor do save"}"#.to_owned(),
+            Self::ModNotFound => r#"{"err":"ModNotFound","desc":"O plugin ou mod não foi encontrado"}"#.to_owned(),
+            Self::ModInvalid => r#"{"err":"ModInvalid","desc":"O arquivo enviado não é um jar"}"#.to_owned(),
+            Self::DatapackNotFound => r#"{"err":"DatapackNotFound","desc":"O datapack não foi encontrado"}"#.to_owned(),
+            Self::DatapackInvalid => r#"{"err":"DatapackInvalid","desc":"O arquivo enviado não é um datapack compactado"}"#.to_owned(),
+            Self::FileNotFound => r#"{"err":"FileNotFound","desc":"O arquivo ou pasta não foi encontrado"}"#.to_owned(),
+            Self::ImportInvalid => r#"{"err":"ImportInvalid","desc":"O arquivo enviado não é um mundo ou servidor compactado"}"#.to_owned(),
+            Self::ImportVersionUnknown => r#"{"err":"ImportVersionUnknown","desc":"Não foi possível descobrir a versão do mundo, escolha a versão para continuar"}"#.to_owned(),
+            Self::DownloadFailed(desc) => {
+                let mut out = String::with_capacity(256);
+                out.push_str(r#"{"err":"DownloadFailed","desc":"Não foi possível baixar a versão","error":"#);
+                append_json_string(&mut out, desc);
+                out.push('}');
+                out
+            },
+            Self::ChecksumMismatch => r#"{"err":"ChecksumMismatch","desc":"O arquivo baixado está corrompido, tente novamente"}"#.to_owned(),
             Self::PropertyNotFound(prop) => {
                 let mut out = String::with_capacity(256);
-                out.push_str(r#"{"err":"PropertyNotFound","desc":"Essa propiedade não existe"},"prop":"#);
-                append_json_string(&mut out, &prop);
+                out.push_str(r#"{"err":"PropertyNotFound","desc":"Essa propiedade não existe","prop":"#);
+                append_json_string(&mut out, prop);
                 out.push('}');
                 out
             },
             Self::PropertyReadOnly(prop) => {
                 let mut out = String::with_capacity(256);
-                out.push_str(r#"{"err":"PropertyReadOnly","desc":"Não é possível escrever para esta propiedade"},"prop":"#);
-                append_json_string(&mut out, &prop);
+                out.push_str(r#"{"err":"PropertyReadOnly","desc":"Não é possível escrever para esta propiedade","prop":"#);
+                append_json_string(&mut out, prop);
                 out.push('}');
                 out
             },
             Self::PropertyInvalid(prop) => {
                 let mut out = String::with_capacity(256);
-                out.push_str(r#"{"err":"PropertyInvalid","desc":"O valor usada para essa propieadade é inválido"},"prop":"#);
-                append_json_string(&mut out, &prop);
+                out.push_str(r#"{"err":"PropertyInvalid","desc":"O valor usada para essa propieadade é inválido","prop":"#);
+                append_json_string(&mut out, prop);
                 out.push('}');
                 out
             },
             Self::BadConfig(prop) => {
                 let mut out = String::with_capacity(256);
-                out.push_str(r#"{"err":"BadConfig","desc":"Essa propieadade está configurada com um valor inválido, reconfigure com um valor válido"},"prop":"#);
-                append_json_string(&mut out, &prop);
+                out.push_str(r#"{"err":"BadConfig","desc":"Essa propieadade está configurada com um valor inválido, reconfigure com um valor válido","prop":"#);
+                append_json_string(&mut out, prop);
                 out.push('}');
                 out
             },
@@ -164,26 +311,31 @@ impl Reply for ApiError
                 InstanceStatus::Online => r#"{"err":"BadInstanceStatus","desc":"O save está ligado","status":"online"}"#,
                 InstanceStatus::Shutdown => r#"{"err":"BadInstanceStatus","desc":"O save está desligando","status":"shutdown"}"#,
                 InstanceStatus::Offline => r#"{"err":"BadInstanceStatus","desc":"O save está desligado","status":"offline"}"#,
+                InstanceStatus::Crashed => r#"{"err":"BadInstanceStatus","desc":"O save travou e está desligado","status":"crashed"}"#,
             }.to_owned(),
             Self::PortInUse => r#"{"err":"PortInUse","desc":"A porta já esta sendo usada por outro save"}"#.to_owned(),
             Self::JavaError(desc) => {
                 let mut out = String::with_capacity(256);
                 out.push_str(r#"{"err":"JavaError","desc":"Ocorreu um erro ao executar o Java","ioerr":"#);
-                append_json_string(&mut out, &desc);
+                append_json_string(&mut out, desc);
+                out.push('}');
+                out
+            },
+            Self::JavaNotFound(required) => {
+                let mut out = String::with_capacity(256);
+                out.push_str(r#"{"err":"JavaNotFound","desc":"Nenhuma instalação do Java compatível com esse save foi encontrada","required":"#);
+                append_json_string(&mut out, required);
                 out.push('}');
                 out
             },
             Self::IOError(desc) => {
                 let mut out = String::with_capacity(256);
                 out.push_str(r#"{"err":"IOError","desc":"Ocorreu um erro ao operar os arquivos","ioerr":"#);
-                append_json_string(&mut out, &desc);
-                out.push_str(r#""java":"#);
-                append_json_string(&mut out, get_java_path().as_str());
+                append_json_string(&mut out, desc);
                 out.push('}');
                 out
             },
-        };
-        json_response_with_status(body, status)
+        }
     }
 }
 
@@ -211,6 +363,12 @@ impl From<std::io::Error> for ApiError {
     }
 }
 
+impl From<zip::result::ZipError> for ApiError {
+    fn from(error: zip::result::ZipError) -> Self {
+        Self::IOError(error.to_string())
+    }
+}
+
 /// creates a json reponse from raw body with the appropiate content-type
 pub fn json_response(body: impl Into<warp::hyper::Body>) -> Response {
     use warp::http::header::CONTENT_TYPE;
@@ -221,6 +379,32 @@ pub fn json_response(body: impl Into<warp::hyper::Body>) -> Response {
     Response::from_parts(parts, body)
 }
 
+/// creates a response that streams the file as a download named `name`
+pub fn file_response(file: tokio::fs::File, name: &str, size: u64) -> Response {
+    use tokio::io::AsyncReadExt;
+    use warp::http::header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE};
+    let chunks = futures::stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut chunk = vec![0; 64 * 1024];
+        match file.read(&mut chunk).await {
+            Ok(0) => None,
+            Ok(length) => {
+                chunk.truncate(length);
+                Some((Ok(chunk), Some(file)))
+            }
+            Err(error) => Some((Err(error), None)),
+        }
+    });
+    let (mut parts, body) = Response::new(warp::hyper::Body::wrap_stream(chunks)).into_parts();
+    parts.headers.append(CONTENT_TYPE, "application/octet-stream".parse().expect("\"application/octet-stream\" is not a valid content-type"));
+    parts.headers.append(CONTENT_LENGTH, size.into());
+    // the names are checked with is_safe, so they have no quotes
+    if let Ok(value) = format!("attachment; filename=\"{name}\"").parse() {
+        parts.headers.append(CONTENT_DISPOSITION, value);
+    }
+    Response::from_parts(parts, body)
+}
+
 /// creates a json reponse from raw body with the appropiate content-type
 fn json_response_with_status(body: impl Into<warp::hyper::Body>, status: warp::http::StatusCode) -> Response {
     use warp::http::header::CONTENT_TYPE;
@@ -233,7 +417,32 @@ fn json_response_with_status(body: impl Into<warp::hyper::Body>, status: warp::h
 }
 
 pub fn now() -> String {
-    chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+    chrono::Local::now().format(TIME_FORMAT).to_string()
+}
+
+/// like `now`, for the time a file was modified
+pub fn format_system_time(time: std::time::SystemTime) -> String {
+    let time: chrono::DateTime<chrono::Local> = time.into();
+    time.format(TIME_FORMAT).to_string()
+}
+
+/// the format of `now`, times in this format can be compared as text
+pub const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// true if `time`, in the format of `now`, is between `from` and `to`, both inclusive
+///
+/// both can be a prefix of the format, `to` then includes everything that starts with it, like a whole day
+pub fn time_in_range(time: &str, from: Option<&str>, to: Option<&str>) -> bool {
+    if from.is_some_and(|from| time < from) {
+        return false;
+    }
+    if let Some(to) = to {
+        let prefix = time.get(..to.len()).unwrap_or(time);
+        if prefix > to {
+            return false;
+        }
+    }
+    true
 }
 
 pub fn append_json_string(out: &mut String, text: &str) {
@@ -242,13 +451,13 @@ pub fn append_json_string(out: &mut String, text: &str) {
         match char {
             '"' => *out += r#"\""#,
             '\\' => *out += r"\\",
-            '\x07' => *out += r"\b",
+            '\x08' => *out += r"\b",
             '\x0C' => *out += r"\f",
             '\n' => *out += r"\n",
             '\r' => *out += r"\r",
             '\t' => *out += r"\t",
             '\0'..='\x1F' | '\x7F' => {
-                *out += r"\x";
+                *out += r"\u00";
                 let upper = char as u8 >> 4;
                 if upper < 10 {
                     out.push((b'0' + upper) as char);
@@ -269,12 +478,12 @@ pub fn append_json_string(out: &mut String, text: &str) {
 }
 
 pub fn append_comma_separated<T>(
-    mut iter: impl Iterator<Item = T>,
+    iter: impl Iterator<Item = T>,
     out: &mut String,
     mut callback: impl FnMut(&mut String, T),
 ) {
     let mut at_least_one_comma = false;
-    while let Some(next) = iter.next() {
+    for next in iter {
         let last_size = out.len();
         callback(out, next);
         if out.len() != last_size {
@@ -0,0 +1,510 @@
+use crate::properties::read_property;
+use crate::state::save;
+use crate::utils::{append_comma_separated, append_json_string, ApiError, TIME_FORMAT};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// the list of every version released by mojang, with a link to the metadata of each one
+const DEFAULT_MANIFEST_URL: &str =
+    "https://piston-meta.mojang.com/mc/game/version_manifest_v2.json";
+
+/// set from `version-manifest` in mc-manager.properties, so a stand-in can be used
+static MANIFEST_URL: std::sync::RwLock<String> = std::sync::RwLock::new(String::new());
+
+/// the last manifest fetched, so the list of versions works without internet
+const MANIFEST_CACHE_FILE: &str = "versions/manifest.json";
+
+/// how long the cached manifest is used before it is fetched again
+const MANIFEST_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// how long to wait for the manifest and the metadata of a version
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    /// the manifest and when it was fetched, locked while fetching so concurrent requests fetch it once
+    static ref MANIFEST: tokio::sync::Mutex<Option<(SystemTime, Arc<Manifest>)>> =
+        tokio::sync::Mutex::new(None);
+}
+
+/// set from `version-hardlink` in mc-manager.properties, saves share the jar of their version instead of a copy
+static HARDLINK: AtomicBool = AtomicBool::new(false);
+
+/// numbers the temporary files, so concurrent downloads of the same version do not mix
+static DOWNLOADS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Deserialize)]
+struct Manifest {
+    versions: Vec<ManifestVersion>,
+}
+
+#[derive(Deserialize)]
+struct ManifestVersion {
+    id: String,
+    /// release, snapshot, old_beta or old_alpha
+    #[serde(rename = "type")]
+    kind: String,
+    /// the metadata of the version
+    url: String,
+    /// like 2023-12-07T12:56:20+00:00
+    #[serde(rename = "releaseTime")]
+    release_time: String,
+}
+
+/// the metadata of a single version, only the parts mc-manager uses
+#[derive(Deserialize)]
+struct VersionMeta {
+    downloads: Downloads,
+}
+
+#[derive(Deserialize)]
+struct Downloads {
+    /// very old versions have no server
+    server: Option<Download>,
+}
+
+#[derive(Deserialize)]
+struct Download {
+    url: String,
+    /// in lowercase hexadecimal
+    sha1: String,
+    size: u64,
+}
+
+/// what a downloaded file must match, the checks that are none are skipped
+#[derive(Default)]
+pub struct Expected<'a> {
+    pub size: Option<u64>,
+    /// in hexadecimal
+    pub sha1: Option<&'a str>,
+    pub sha256: Option<&'a str>,
+    /// the file must be a valid zip, for jars that are published without a checksum we can use
+    pub jar: bool,
+}
+
+/// empty uses the manifest of mojang
+pub fn set_manifest_url(url: &str) {
+    let url = if url.is_empty() {
+        DEFAULT_MANIFEST_URL
+    } else {
+        url
+    };
+    *MANIFEST_URL.write().expect("MANIFEST_URL lock is poisoned") = url.to_owned();
+}
+
+pub fn set_hardlink(hardlink: bool) {
+    HARDLINK.store(hardlink, Ordering::Relaxed);
+}
+
+/// puts the jar of an installed version at `path`, as a hardlink if enabled and possible, otherwise as a copy
+pub fn install(version: &str, path: &str) -> std::io::Result<()> {
+    let jar = format!("versions/{version}.jar");
+    if HARDLINK.load(Ordering::Relaxed) {
+        match std::fs::hard_link(&jar, path) {
+            Ok(()) => return Ok(()),
+            // like when saves are in another filesystem
+            Err(error) => println!("[!] Could not hardlink {jar}, copying it instead: {error}"),
+        }
+    }
+    std::fs::copy(&jar, path).map(|_| ())
+}
+
+/// returns a valid json with the jars in the versions folder, their size, and the saves of each version
+///
+/// only the saves for which `visible` returns true are listed, but all of them count as using a version
+pub fn list_installed(visible: impl Fn(&str) -> bool) -> Result<String, ApiError> {
+    let usage = usage()?;
+    let mut versions = installed()?;
+    versions.sort();
+    let mut out = String::with_capacity(128 * versions.len() + 32);
+    let mut total = 0;
+    out += r#"{"versions":["#;
+    append_comma_separated(versions.iter(), &mut out, |out, version| {
+        let size = std::fs::metadata(format!("versions/{version}.jar")).map_or(0, |x| x.len());
+        total += size;
+        let saves = usage.get(version).map_or(&[][..], |x| x.as_slice());
+        *out += r#"{"id":"#;
+        append_json_string(out, version);
+        *out += r#","size":"#;
+        *out += &size.to_string();
+        *out += r#","used":"#;
+        *out += if saves.is_empty() { "false" } else { "true" };
+        *out += r#","saves":["#;
+        append_comma_separated(
+            saves.iter().filter(|save| visible(save)),
+            out,
+            |out, save| append_json_string(out, save),
+        );
+        *out += "]}";
+    });
+    out += r#"],"total":"#;
+    out += &total.to_string();
+    out += "}";
+    Ok(out)
+}
+
+/// fails with VersionDowngrade if `to` was released before `from`, or if that can not be told, unless forced
+///
+/// worlds opened by an older version than the one that last saved them get corrupted
+pub async fn check_upgrade(from: &str, to: &str, force: bool) -> Result<(), ApiError> {
+    if force || from == to {
+        return Ok(());
+    }
+    let manifest = manifest(false).await?;
+    let released = |id: &str| {
+        let version = manifest.versions.iter().find(|x| x.id == id)?;
+        chrono::DateTime::parse_from_rfc3339(&version.release_time).ok()
+    };
+    if released(to).is_none() && !installed()?.iter().any(|x| x == to) {
+        return Err(ApiError::VersionNotFound);
+    }
+    match (released(from), released(to)) {
+        (Some(from), Some(to)) if to >= from => Ok(()),
+        _ => Err(ApiError::VersionDowngrade),
+    }
+}
+
+/// removes the jar of a version, fails with VersionInUse if a save was created with it
+pub fn delete(version: &str) -> Result<(), ApiError> {
+    if !is_valid_id(version) || !installed()?.iter().any(|x| x == version) {
+        return Err(ApiError::VersionNotFound);
+    }
+    if usage()?.contains_key(version) {
+        return Err(ApiError::VersionInUse);
+    }
+    std::fs::remove_file(format!("versions/{version}.jar"))?;
+    Ok(())
+}
+
+/// the saves of each version, from their `mc-manager-server-version`
+fn usage() -> Result<BTreeMap<String, Vec<String>>, ApiError> {
+    let mut usage: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for name in save::iter()? {
+        let name = name?;
+        let path = format!("saves/{name}/server.properties");
+        // folders that are not saves have no properties
+        let Ok(Some(version)) = read_property(path, "mc-manager-server-version") else {
+            continue;
+        };
+        usage
+            .entry(version.trim().to_owned())
+            .or_default()
+            .push(name);
+    }
+    Ok(usage)
+}
+
+fn is_valid_id(version: &str) -> bool {
+    !version.is_empty() && !version.starts_with('.') && !version.contains(['/', '\\'])
+}
+
+/// returns a valid json with the versions in the manifest, newest first, and the ones only found in the versions folder
+///
+/// `refresh` fetches the manifest even if the cached one has not expired, and fails if it can not be fetched,
+/// otherwise an expired manifest, or just the installed versions, are listed when it can not be fetched
+pub async fn list(refresh: bool) -> Result<String, ApiError> {
+    let versions = match manifest(refresh).await {
+        Ok(manifest) => Some(manifest),
+        Err(error) if refresh => return Err(error),
+        Err(_) => None,
+    };
+    let versions = versions.as_ref().map_or(&[][..], |x| x.versions.as_slice());
+    let mut installed = installed()?;
+    let mut out = String::with_capacity(128 * versions.len() + 16);
+    out += "[";
+    append_comma_separated(versions.iter(), &mut out, |out, version| {
+        let is_installed = match installed.iter().position(|x| *x == version.id) {
+            Some(index) => {
+                installed.swap_remove(index);
+                true
+            }
+            None => false,
+        };
+        *out += r#"{"id":"#;
+        append_json_string(out, &version.id);
+        *out += r#","type":"#;
+        append_json_string(out, &version.kind);
+        *out += r#","released":"#;
+        match chrono::DateTime::parse_from_rfc3339(&version.release_time) {
+            Ok(time) => {
+                let time = time.with_timezone(&chrono::Local);
+                append_json_string(out, &time.format(TIME_FORMAT).to_string())
+            }
+            Err(_) => *out += "null",
+        }
+        *out += r#","installed":"#;
+        *out += if is_installed { "true" } else { "false" };
+        *out += "}";
+    });
+    // jars put in the versions folder by hand
+    installed.sort();
+    for id in &installed {
+        if out.len() > 1 {
+            out += ",";
+        }
+        out += r#"{"id":"#;
+        append_json_string(&mut out, id);
+        out += r#","type":"local","released":null,"installed":true}"#;
+    }
+    out += "]";
+    Ok(out)
+}
+
+/// downloads the server jar of the version into the versions folder, unless it is already there
+pub async fn download(version: &str) -> Result<(), ApiError> {
+    if !is_valid_id(version) {
+        return Err(ApiError::VersionNotFound);
+    }
+    let path = format!("versions/{version}.jar");
+    if std::fs::metadata(&path).is_ok_and(|x| x.is_file()) {
+        return Ok(());
+    }
+    let mut known = manifest(false).await?;
+    if !known.versions.iter().any(|x| x.id == version) {
+        // it may have been released after the manifest was cached
+        known = manifest(true).await?;
+    }
+    let Some(entry) = known.versions.iter().find(|x| x.id == version) else {
+        return Err(ApiError::VersionNotFound);
+    };
+    let meta: VersionMeta = fetch_json(&entry.url).await?;
+    let Some(server) = meta.downloads.server else {
+        return Err(ApiError::VersionNotFound);
+    };
+    download_file(
+        &server.url,
+        &path,
+        Expected {
+            size: Some(server.size),
+            sha1: Some(&server.sha1),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// downloads `url` to `path` through a temporary file, which is only renamed into place if it matches `expected`
+///
+/// a failed download never leaves a file at `path`, so it never looks like an installed version
+pub async fn download_file(url: &str, path: &str, expected: Expected<'_>) -> Result<(), ApiError> {
+    let partial = format!("{path}.{}.part", DOWNLOADS.fetch_add(1, Ordering::Relaxed));
+    async {
+        let mut file = std::fs::File::create(&partial)?;
+        let mut response = reqwest::Client::new()
+            .get(url)
+            .send()
+            .await
+            .and_then(|x| x.error_for_status())
+            .map_err(|x| ApiError::DownloadFailed(x.to_string()))?;
+        let mut sha1 = Sha1::new();
+        let mut sha256 = Sha256::new();
+        let mut size = 0;
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|x| ApiError::DownloadFailed(x.to_string()))?
+        {
+            size += chunk.len() as u64;
+            if expected.size.is_some_and(|expected| size > expected) {
+                return Err(ApiError::ChecksumMismatch);
+            }
+            if expected.sha1.is_some() {
+                sha1.update(&chunk);
+            }
+            if expected.sha256.is_some() {
+                sha256.update(&chunk);
+            }
+            file.write_all(&chunk)?;
+        }
+        let matches = |hash: String, expected: Option<&str>| {
+            expected.is_none_or(|expected| hash.eq_ignore_ascii_case(expected.trim()))
+        };
+        if expected.size.is_some_and(|expected| size != expected)
+            || !matches(format!("{:x}", sha1.finalize()), expected.sha1)
+            || !matches(format!("{:x}", sha256.finalize()), expected.sha256)
+        {
+            return Err(ApiError::ChecksumMismatch);
+        }
+        file.sync_all()?;
+        // windows can not rename open files
+        drop(file);
+        if expected.jar && zip::ZipArchive::new(std::fs::File::open(&partial)?).is_err() {
+            return Err(ApiError::DownloadFailed(format!("{url} is not a jar")));
+        }
+        std::fs::rename(&partial, path)?;
+        Ok(())
+    }
+    .await
+    .inspect_err(|_| {
+        let _ = std::fs::remove_file(&partial);
+    })
+}
+
+/// the names of the jars in the versions folder, without the extension
+fn installed() -> Result<Vec<String>, ApiError> {
+    let mut versions = Vec::new();
+    for entry in std::fs::read_dir("versions")? {
+        let entry = entry?;
+        if let Some(filename) = entry.file_name().to_str() {
+            if matches!(entry.metadata(), Ok(md) if md.is_file()) {
+                if let Some(name) = filename.strip_suffix(".jar") {
+                    versions.push(name.to_owned());
+                }
+            }
+        }
+    }
+    Ok(versions)
+}
+
+/// the cached manifest, fetched again if it expired or if `refresh` is set
+///
+/// an expired manifest is used if it can not be fetched, unless `refresh` is set
+async fn manifest(refresh: bool) -> Result<Arc<Manifest>, ApiError> {
+    let mut cache = MANIFEST.lock().await;
+    if cache.is_none() {
+        *cache = read_cached_manifest();
+    }
+    if let Some((fetched, manifest)) = &*cache {
+        if !refresh && fetched.elapsed().is_ok_and(|x| x < MANIFEST_TTL) {
+            return Ok(manifest.clone());
+        }
+    }
+    let url = MANIFEST_URL
+        .read()
+        .expect("MANIFEST_URL lock is poisoned")
+        .clone();
+    let fetched = async {
+        let bytes = fetch_bytes(&url).await?;
+        let manifest: Manifest = parse_json(&url, &bytes)?;
+        Ok::<_, ApiError>((bytes, manifest))
+    }
+    .await;
+    match fetched {
+        Ok((bytes, manifest)) => {
+            let partial = format!("{MANIFEST_CACHE_FILE}.part");
+            let written = std::fs::write(&partial, &bytes)
+                .and_then(|()| std::fs::rename(&partial, MANIFEST_CACHE_FILE));
+            if let Err(error) = written {
+                println!("[!] Could not cache the version manifest: {error}");
+            }
+            let manifest = Arc::new(manifest);
+            *cache = Some((SystemTime::now(), manifest.clone()));
+            Ok(manifest)
+        }
+        Err(error) => match &*cache {
+            Some((_, manifest)) if !refresh => {
+                println!("[!] Could not fetch the version manifest, using the cached one");
+                Ok(manifest.clone())
+            }
+            _ => Err(error),
+        },
+    }
+}
+
+/// the manifest cached on disk, from when it was written
+fn read_cached_manifest() -> Option<(SystemTime, Arc<Manifest>)> {
+    let bytes = std::fs::read(MANIFEST_CACHE_FILE).ok()?;
+    let fetched = std::fs::metadata(MANIFEST_CACHE_FILE)
+        .and_then(|x| x.modified())
+        .ok()?;
+    let manifest = serde_json::from_slice(&bytes).ok()?;
+    Some((fetched, Arc::new(manifest)))
+}
+
+pub async fn fetch_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T, ApiError> {
+    parse_json(url, &fetch_bytes(url).await?)
+}
+
+async fn fetch_bytes(url: &str) -> Result<Vec<u8>, ApiError> {
+    let response = async {
+        reqwest::Client::builder()
+            .timeout(FETCH_TIMEOUT)
+            .build()?
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await
+    }
+    .await
+    .map_err(|error| ApiError::DownloadFailed(error.to_string()))?;
+    Ok(response.to_vec())
+}
+
+fn parse_json<T: serde::de::DeserializeOwned>(url: &str, response: &[u8]) -> Result<T, ApiError> {
+    serde_json::from_slice(response)
+        .map_err(|error| ApiError::DownloadFailed(format!("invalid response from {url}: {error}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_ids() {
+        for id in ["1.20.4", "24w14a", "1.14 Pre-Release 1", "b1.7.3"] {
+            assert!(is_valid_id(id), "{id}");
+        }
+        for id in ["", ".", "..", ".hidden", "../1.20.4", "a/b", "a\\b"] {
+            assert!(!is_valid_id(id), "{id}");
+        }
+    }
+
+    #[test]
+    fn parses_the_manifest() {
+        let response = br#"{
+            "latest": {"release": "1.20.4", "snapshot": "24w14a"},
+            "versions": [
+                {"id": "24w14a", "type": "snapshot", "url": "https://example.com/24w14a.json",
+                 "time": "2024-04-03T12:00:00+00:00", "releaseTime": "2024-04-03T11:00:00+00:00"},
+                {"id": "1.20.4", "type": "release", "url": "https://example.com/1.20.4.json",
+                 "time": "2023-12-07T12:56:20+00:00", "releaseTime": "2023-12-07T12:56:20+00:00"}
+            ]
+        }"#;
+        let manifest: Manifest = parse_json("manifest", response).ok().unwrap();
+        assert_eq!(manifest.versions.len(), 2);
+        assert_eq!(manifest.versions[1].id, "1.20.4");
+        assert_eq!(manifest.versions[1].kind, "release");
+        assert_eq!(manifest.versions[1].url, "https://example.com/1.20.4.json");
+        assert_eq!(
+            manifest.versions[1].release_time,
+            "2023-12-07T12:56:20+00:00"
+        );
+    }
+
+    #[test]
+    fn refuses_an_invalid_manifest() {
+        for response in [
+            &b"<html></html>"[..],
+            br#"{"versions": [{"id": "1.20.4"}]}"#,
+        ] {
+            let result = parse_json::<Manifest>("manifest", response);
+            assert!(matches!(result, Err(ApiError::DownloadFailed(_))));
+        }
+    }
+
+    #[test]
+    fn parses_the_server_download() {
+        let response = br#"{"id": "1.20.4", "downloads": {
+            "client": {"sha1": "aa", "size": 1, "url": "https://example.com/client.jar"},
+            "server": {"sha1": "8dd1a28015f51b1803213892b50b7b4fc76e594d", "size": 49150256,
+                       "url": "https://example.com/server.jar"}
+        }}"#;
+        let meta: VersionMeta = parse_json("meta", response).ok().unwrap();
+        let server = meta.downloads.server.unwrap();
+        assert_eq!(server.url, "https://example.com/server.jar");
+        assert_eq!(server.sha1, "8dd1a28015f51b1803213892b50b7b4fc76e594d");
+        assert_eq!(server.size, 49150256);
+        // very old versions have only a client
+        let response = br#"{"downloads": {"client": {"sha1": "aa", "size": 1, "url": "u"}}}"#;
+        let meta: VersionMeta = parse_json("meta", response).ok().unwrap();
+        assert!(meta.downloads.server.is_none());
+    }
+}
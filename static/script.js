@@ -42,9 +42,13 @@ function api(method, path, payload) {
                 }
             }
             document.body.classList.toggle("noconn", r.status === 0);
+            if (r.status === 401) {
+                location.href = "/login.html";
+                return;
+            }
             if (r.status === 200) {
                 resolve(response);
-            } else if (r.status === 400 || r.status === 500) {
+            } else if (r.status === 400 || r.status === 403 || r.status === 500) {
                 reject(response);
             } else if (r.status === 0) {
                 reject({
@@ -219,7 +223,8 @@ function show_screen(screen, within_popstate_handler) {
         let output = document.getElementById("console-output");
         let name_encoded = encodeURI(selected);
         let cursor = 0;
-        let api_console = `ws://${window.location.hostname}:${window.location.port}/api/console`;
+        let ws_protocol = window.location.protocol === "https:" ? "wss:" : "ws:";
+        let api_console = `${ws_protocol}//${window.location.host}/api/console`;
         clear_elem(output);
         function onerror(error) {
             ws.close();
@@ -232,6 +237,11 @@ function show_screen(screen, within_popstate_handler) {
             handle_error(error, "ws");
         }
         function onmessage(ev) {
+            if (typeof ev.data === "string") {
+                // the server skipped output that was lost
+                cursor = JSON.parse(ev.data).offset;
+                return;
+            }
             cursor += ev.data.size;
             ev.data.text().then(function(text) {
                 let lines = text.split('\n');
@@ -267,9 +277,10 @@ function update_filter() {
 
 function update_status(status) {
     foreach(Object.values(saves), function(save) {
-        let new_status = status[save.name];
-        if (new_status === undefined) {
-            new_status = "cold";
+        let new_status = "cold";
+        if (status[save.name] !== undefined) {
+            new_status = status[save.name].status;
+            save.crashes = status[save.name].crashes;
         }
         if (save.status !== new_status) {
             let elem = saves_elem[save.name];
@@ -348,12 +359,12 @@ function main() {
         show_screen("create-screen");
     });
     document.getElementById("saves-button-modify").addEventListener("click", function() {
-        if (selected !== null && (saves[selected].status === "offline" || saves[selected].status === "cold")) {
+        if (selected !== null && (saves[selected].status === "offline" || saves[selected].status === "crashed" || saves[selected].status === "cold")) {
             show_screen("modify-screen");
         }
     });
     document.getElementById("saves-button-delete").addEventListener("click", function() {
-        if (selected !== null && (saves[selected].status === "offline" || saves[selected].status === "cold")) {
+        if (selected !== null && (saves[selected].status === "offline" || saves[selected].status === "crashed" || saves[selected].status === "cold")) {
             show_screen("delete-screen");
         }
     });
@@ -509,14 +520,15 @@ function main() {
                 let elem = document.createElement("button");
                 let span = document.createElement("span");
                 elem.append(span);
-                elem.classList.add("version", "wide");
-                span.innerText = version;
+                elem.classList.add("version", "wide", version.type);
+                span.innerText = version.type === "release" ? version.id : `${version.id} (${version.type})`;
+                elem.title = version.released || "";
                 area.append(elem);
                 elem.addEventListener("click", function() {
                     if (typeof show_version_screen_callback === "function") {
                         let callback = show_version_screen_callback;
                         show_version_screen_callback = null;
-                        callback(version);
+                        callback(version.id);
                     }
                 });
             });
@@ -545,9 +557,9 @@ function main() {
 function start_stop_save(name) {
     let save = saves[name];
     let save_div = saves_elem[name];
-    if (save.status === "offline" || save.status === "cold") {
+    if (save.status === "offline" || save.status === "crashed" || save.status === "cold") {
         api_start_save(name).then(function(response) {
-            save_div.classList.remove("offline", "cold");
+            save_div.classList.remove("offline", "crashed", "cold");
             save_div.classList.add("loading");
             save.status = "loading";
             if (name === selected) select_save(selected);
@@ -588,7 +600,7 @@ function select_save(name) {
     } else if (elem.classList.contains("shutdown")) {
         enable("saves-button-console");
         play_button_caption.innerText = "Parar mundo";
-    } else if (elem.classList.contains("offline")) {
+    } else if (elem.classList.contains("offline") || elem.classList.contains("crashed")) {
         enable("saves-button-play", "saves-button-modify", "saves-button-delete", "saves-button-console");
         play_button_caption.innerText = "Iniciar mundo";
     }